@@ -0,0 +1,85 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use schism::lex::Scanner;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A global allocator that counts allocations, so that the benchmark
+/// can report how many each way of scanning makes.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        return System.alloc(layout);
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Build a large, all-ASCII source file out of many copies of a
+/// function that uses most kinds of token.
+fn synthetic_source(functions: usize) -> String {
+    let mut source = String::new();
+    for i in 0..functions {
+        source.push_str(&format!(
+            "fun step_{} ( @A int [`t]List -- @A str ) is
+    dup 0 > if
+        1_000 + \"label\" swap <- render
+    else
+        [[ ( int -- int ) 2 * ]] apply 3.25e2 drop
+    end
+    #[ int | 1, 2, {} ]# length
+end
+
+",
+            i, i
+        ));
+    }
+    return source;
+}
+
+fn scan_owned(source: &str) -> usize {
+    return Scanner::new("bench".to_string(), source).count();
+}
+
+fn scan_borrowed(source: &str) -> usize {
+    let mut scanner = Scanner::new("bench".to_string(), source);
+    return std::iter::from_fn(|| scanner.scan_borrowed_token()).count();
+}
+
+/// Count the allocations made by one scan of the source.
+fn allocations(scan: fn(&str) -> usize, source: &str) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(scan(source));
+    return ALLOCATIONS.load(Ordering::Relaxed) - before;
+}
+
+fn scan_benchmark(c: &mut Criterion) {
+    let source = synthetic_source(2000);
+    println!(
+        "allocations per scan: {} with owned tokens, {} with borrowed tokens",
+        allocations(scan_owned, &source),
+        allocations(scan_borrowed, &source)
+    );
+    // Both ways of scanning go in one group, over the same source, so
+    // that criterion reports them side by side.
+    let mut group = c.benchmark_group("scan ascii source");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("owned tokens", |b| {
+        b.iter(|| scan_owned(black_box(&source)))
+    });
+    group.bench_function("borrowed tokens", |b| {
+        b.iter(|| scan_borrowed(black_box(&source)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, scan_benchmark);
+criterion_main!(benches);
@@ -1,4 +1,5 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::twist::{Twist, Twistable};
+use std::cell::Cell;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Symbol(pub String);
@@ -17,6 +18,20 @@ pub trait Renderable {
     }
 }
 
+/// The range of byte offsets in the source that a declaration or
+/// expression was parsed from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        return Span { start, end };
+    }
+}
+
 impl Symbol {
     fn to_string(&self) -> String {
         match self {
@@ -46,6 +61,31 @@ impl Identifier {
     }
 }
 
+impl Twistable for Identifier {
+    fn twist(&self) -> Twist {
+        return match self {
+            Self::Qualified(symbols) => {
+                let (name, path) = symbols.split_last().unwrap();
+                Twist::obj(
+                    "ScopedName",
+                    vec![
+                        Twist::attr(
+                            "path",
+                            path.iter()
+                                .map(|s| s.to_string())
+                                .collect::<Vec<String>>()
+                                .join("::"),
+                        ),
+                        Twist::attr("name", name.to_string()),
+                    ],
+                )
+            }
+            Self::Simple(s) => Twist::obj("LocalName", vec![Twist::attr("name", s.to_string())]),
+            Self::System(s) => Twist::obj("SystemName", vec![Twist::attr("name", s.clone())]),
+        };
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Sect {
     pub uses: Vec<UseDecl>,
@@ -71,6 +111,7 @@ impl Renderable for Sect {
 pub struct UseDecl {
     pub sect: Identifier,
     pub names: Option<Vec<Symbol>>,
+    pub span: Span,
 }
 
 impl Renderable for UseDecl {
@@ -99,6 +140,20 @@ pub enum Decl {
     Struct(StructDecl),
     Function(FunctionDecl),
     Var(VarDecl),
+    Alias(AliasDecl),
+    Const(ConstDecl),
+}
+
+impl Decl {
+    pub fn span(&self) -> Span {
+        return match self {
+            Self::Struct(s) => s.span,
+            Self::Function(f) => f.span,
+            Self::Var(v) => v.span,
+            Self::Alias(a) => a.span,
+            Self::Const(c) => c.span,
+        };
+    }
 }
 
 impl Renderable for Decl {
@@ -107,6 +162,8 @@ impl Renderable for Decl {
             Self::Struct(s) => s.render_into(target, indent),
             Self::Function(f) => f.render_into(target, indent),
             Self::Var(v) => v.render_into(target, indent),
+            Self::Alias(a) => a.render_into(target, indent),
+            Self::Const(c) => c.render_into(target, indent),
         }
     }
 }
@@ -137,6 +194,7 @@ pub struct StructDecl {
     pub type_params: Option<Vec<TypeParam>>,
     pub fields: Vec<TypedIdentifier>,
     pub methods: Vec<MethodDecl>,
+    pub span: Span,
 }
 
 impl Renderable for StructDecl {
@@ -200,6 +258,7 @@ pub struct MethodDecl {
     pub name: Symbol,
     pub effect: StackEffect,
     pub body: Vec<Expr>,
+    pub span: Span,
 }
 
 impl Renderable for MethodDecl {
@@ -230,6 +289,7 @@ pub struct FunctionDecl {
     pub type_params: Option<Vec<TypeParam>>,
     pub signature: StackEffect,
     pub body: Vec<Expr>,
+    pub span: Span,
 }
 
 impl Renderable for FunctionDecl {
@@ -265,6 +325,7 @@ pub struct VarDecl {
     pub name: Symbol,
     pub s_type: SType,
     pub init_value: Vec<Expr>,
+    pub span: Span,
 }
 
 impl Renderable for VarDecl {
@@ -283,6 +344,65 @@ impl Renderable for VarDecl {
     }
 }
 
+/// A named constant. Unlike a var, a constant has no slot that can
+/// be updated: its body is evaluated to produce a single value.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConstDecl {
+    pub name: Symbol,
+    pub value_type: SType,
+    pub body: Vec<Expr>,
+    pub span: Span,
+}
+
+impl Renderable for ConstDecl {
+    fn render_into(&self, target: &mut String, indent: usize) {
+        self.indent(target, indent);
+        target.push_str("const ");
+        target.push_str(&self.name.to_string());
+        target.push_str(": ");
+        target.push_str(&self.value_type.to_string());
+        target.push_str(" is\n");
+        for e in &self.body {
+            e.render_into(target, indent + 1)
+        }
+        self.indent(target, indent);
+        target.push_str("end\n")
+    }
+}
+
+/// A type alias, giving a name to a (possibly parametric) type.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AliasDecl {
+    pub name: Symbol,
+    pub type_params: Option<Vec<TypeParam>>,
+    pub aliased: SType,
+    pub span: Span,
+}
+
+impl Renderable for AliasDecl {
+    fn render_into(&self, target: &mut String, indent: usize) {
+        self.indent(target, indent);
+        target.push_str("alias ");
+        match &self.type_params {
+            Some(tps) => {
+                target.push_str("[");
+                target.push_str(
+                    &tps.iter()
+                        .map(|tp| tp.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                );
+                target.push_str("]");
+            }
+            None => (),
+        }
+        target.push_str(&self.name.to_string());
+        target.push_str(" is ");
+        target.push_str(&self.aliased.to_string());
+        target.push_str(" end\n")
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     FunCall(FunCallExpr),
@@ -292,11 +412,35 @@ pub enum Expr {
     Loop(LoopExpr),
     MethodCall(MethodCallExpr),
     Block(BlockExpr),
-    IntLit(i64),
-    FloatLit(f64),
-    StringLit(String),
-    CharLit(char),
+    IntLit(i64, Span),
+    FloatLit(String, Span),
+    StringLit(String, Span),
+    CharLit(char, Span),
+    BoolLit(bool, Span),
     Local(LocalExpr),
+    // A reference to a local, which the parser can't tell apart
+    // from a function call; see [crate::resolve::resolve_locals].
+    LocalRef(LocalExpr),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        return match self {
+            Self::FunCall(f) => f.span,
+            Self::List(l) => l.span,
+            Self::Map(m) => m.span,
+            Self::Cond(c) => c.span,
+            Self::Loop(l) => l.span,
+            Self::MethodCall(m) => m.span,
+            Self::Block(b) => b.span,
+            Self::IntLit(_, span)
+            | Self::FloatLit(_, span)
+            | Self::StringLit(_, span)
+            | Self::CharLit(_, span)
+            | Self::BoolLit(_, span) => *span,
+            Self::Local(l) | Self::LocalRef(l) => l.span,
+        };
+    }
 }
 
 impl Renderable for Expr {
@@ -309,29 +453,40 @@ impl Renderable for Expr {
             Self::Loop(l) => l.render_into(target, indent),
             Self::MethodCall(m) => m.render_into(target, indent),
             Self::Block(b) => b.render_into(target, indent),
-            Self::IntLit(i) => {
+            Self::IntLit(i, _) => {
                 self.indent(target, indent);
                 target.push_str(&i.to_string());
                 target.push_str("\n")
             }
-            Self::FloatLit(f) => {
+            Self::FloatLit(f, _) => {
                 self.indent(target, indent);
-                target.push_str(&f.to_string());
+                target.push_str(f);
                 target.push_str("\n")
             }
-            Self::StringLit(s) => {
+            Self::StringLit(s, _) => {
                 self.indent(target, indent);
                 target.push('"');
                 target.push_str(s);
                 target.push_str("\"\n")
             }
-            Self::CharLit(c) => {
+            Self::CharLit(c, _) => {
                 self.indent(target, indent);
                 target.push_str("'");
                 target.push(*c);
                 target.push_str("'\n");
             }
+            Self::BoolLit(b, _) => {
+                self.indent(target, indent);
+                target.push_str(&b.to_string());
+                target.push_str("\n")
+            }
             Self::Local(l) => l.render_into(target, indent),
+            Self::LocalRef(l) => {
+                self.indent(target, indent);
+                target.push_str("localref ");
+                target.push_str(&l.name.to_string());
+                target.push_str("\n")
+            }
         }
     }
 }
@@ -366,10 +521,31 @@ impl Renderable for SType {
     }
 }
 
+impl Twistable for SType {
+    fn twist(&self) -> Twist {
+        return match self {
+            Self::Simple(id) => Twist::obj("SimpleType", vec![Twist::val("name", id.twist())]),
+            Self::Parametric(params, id) => Twist::obj(
+                "ParametricType",
+                vec![
+                    Twist::arr("params", params.iter().map(|p| p.twist()).collect()),
+                    Twist::val("name", id.twist()),
+                ],
+            ),
+            Self::Function(f) => Twist::obj("FunctionType", vec![Twist::val("effect", f.twist())]),
+            Self::TypeVar(t) => Twist::obj("TypeVar", vec![Twist::attr("name", t.to_string())]),
+        };
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct StackEffect {
     pub before: StackImage,
     pub after: StackImage,
+    // The effect domains, like IO, that running the code can touch,
+    // from the "uses" clause of a signature.
+    pub effect_domains: Vec<Identifier>,
+    pub span: Span,
 }
 
 impl Renderable for StackEffect {
@@ -379,6 +555,33 @@ impl Renderable for StackEffect {
         target.push_str(" -- ");
         target.push_str(&self.after.to_string());
         target.push_str(")");
+        if !self.effect_domains.is_empty() {
+            target.push_str(" uses ");
+            target.push_str(
+                &self
+                    .effect_domains
+                    .iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            );
+        }
+    }
+}
+
+impl Twistable for StackEffect {
+    fn twist(&self) -> Twist {
+        return Twist::obj(
+            "StackEffect",
+            vec![
+                Twist::val("before", self.before.twist()),
+                Twist::val("after", self.after.twist()),
+                Twist::arr(
+                    "domains",
+                    self.effect_domains.iter().map(|d| d.twist()).collect(),
+                ),
+            ],
+        );
     }
 }
 
@@ -386,12 +589,19 @@ impl Renderable for StackEffect {
 pub struct StackImage {
     pub stack_var: Symbol,
     pub stack: Vec<SType>,
+    // The name given to the single result of an effect, like "sum"
+    // in "( int int -- sum -> int )". Always None on the before side.
+    pub result_name: Option<Symbol>,
 }
 
 impl Renderable for StackImage {
     fn render_into(&self, target: &mut String, _: usize) {
         target.push_str(&self.stack_var.to_string());
         target.push_str(" ");
+        if let Some(name) = &self.result_name {
+            target.push_str(&name.to_string());
+            target.push_str(" -> ");
+        }
         target.push_str(
             &self
                 .stack
@@ -403,16 +613,38 @@ impl Renderable for StackImage {
     }
 }
 
-static IMG_VARIABLE_INDEX: AtomicUsize = AtomicUsize::new(0);
+impl Twistable for StackImage {
+    fn twist(&self) -> Twist {
+        let mut children = vec![Twist::attr("context", self.stack_var.to_string())];
+        if let Some(name) = &self.result_name {
+            children.push(Twist::attr("result_name", name.to_string()));
+        }
+        children.push(Twist::arr(
+            "stack",
+            self.stack.iter().map(|t| t.twist()).collect(),
+        ));
+        return Twist::obj("StackImage", children);
+    }
+}
+
+thread_local! {
+    static IMG_VARIABLE_INDEX: Cell<usize> = const { Cell::new(0) };
+}
 
 impl StackImage {
+    /// Make up a name for the context variable of a stack image that
+    /// didn't give one. The names only need to be unique within the
+    /// sect they're parsed from, and a parse runs on a single thread,
+    /// so the counter is kept per thread; the parse helpers in the
+    /// crate root reset it, so that parsing the same text always
+    /// produces the same names.
     pub fn unique_image_var() -> Symbol {
-        let idx = IMG_VARIABLE_INDEX.fetch_add(1, Ordering::Relaxed);
+        let idx = IMG_VARIABLE_INDEX.with(|i| i.replace(i.get() + 1));
         return Symbol(format!("@_{}", idx));
     }
 
     pub fn reset_index() {
-        IMG_VARIABLE_INDEX.store(0, Ordering::Relaxed)
+        IMG_VARIABLE_INDEX.with(|i| i.set(0))
     }
 }
 
@@ -421,6 +653,7 @@ impl StackImage {
 pub struct FunCallExpr {
     pub id: Identifier,
     pub type_args: Option<Vec<SType>>,
+    pub span: Span,
 }
 
 impl Renderable for FunCallExpr {
@@ -448,6 +681,7 @@ impl Renderable for FunCallExpr {
 pub struct ListExpr {
     pub value_type: SType,
     pub values: Vec<Vec<Expr>>,
+    pub span: Span,
 }
 
 impl Renderable for ListExpr {
@@ -475,6 +709,7 @@ pub struct MapExpr {
     pub key_type: SType,
     pub value_type: SType,
     pub values: Vec<(Vec<Expr>, Vec<Expr>)>,
+    pub span: Span,
 }
 
 impl Renderable for MapExpr {
@@ -511,7 +746,9 @@ impl Renderable for MapExpr {
 #[derive(Debug, PartialEq, Clone)]
 pub struct CondExpr {
     pub true_block: Vec<Expr>,
+    // Empty if the cond has no "else".
     pub false_block: Vec<Expr>,
+    pub span: Span,
 }
 
 impl Renderable for CondExpr {
@@ -521,10 +758,12 @@ impl Renderable for CondExpr {
         for t in &self.true_block {
             t.render_into(target, indent + 1)
         }
-        self.indent(target, indent);
-        target.push_str("else\n");
-        for f in &self.false_block {
-            f.render_into(target, indent + 1)
+        if !self.false_block.is_empty() {
+            self.indent(target, indent);
+            target.push_str("else\n");
+            for f in &self.false_block {
+                f.render_into(target, indent + 1)
+            }
         }
         self.indent(target, indent);
         target.push_str("end\n");
@@ -534,6 +773,7 @@ impl Renderable for CondExpr {
 #[derive(Debug, PartialEq, Clone)]
 pub struct LoopExpr {
     pub body: Vec<Expr>,
+    pub span: Span,
 }
 
 impl Renderable for LoopExpr {
@@ -551,6 +791,7 @@ impl Renderable for LoopExpr {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct MethodCallExpr {
     pub sym: Symbol,
+    pub span: Span,
 }
 
 impl Renderable for MethodCallExpr {
@@ -566,6 +807,7 @@ impl Renderable for MethodCallExpr {
 pub struct BlockExpr {
     pub effect: StackEffect,
     pub body: Vec<Expr>,
+    pub span: Span,
 }
 
 impl Renderable for BlockExpr {
@@ -586,6 +828,7 @@ impl Renderable for BlockExpr {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct LocalExpr {
     pub name: Symbol,
+    pub span: Span,
 }
 
 impl Renderable for LocalExpr {
@@ -0,0 +1,40 @@
+use crate::ast::Span;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found by one of the checks on the AST. Unlike an
+/// [crate::error::Error], which stops processing, a diagnostic is
+/// collected and processing continues; only diagnostics with a
+/// severity of Error should make a build fail.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(span: Span, message: String) -> Diagnostic {
+        return Diagnostic {
+            severity: Severity::Warning,
+            span,
+            message,
+        };
+    }
+
+    pub fn error(span: Span, message: String) -> Diagnostic {
+        return Diagnostic {
+            severity: Severity::Error,
+            span,
+            message,
+        };
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    return diagnostics.iter().any(|d| d.severity == Severity::Error);
+}
@@ -0,0 +1,124 @@
+use crate::ast::{Expr, FunctionDecl, Identifier, SType, StackEffect, StackImage};
+use crate::diagnostic::Diagnostic;
+use std::collections::{HashMap, HashSet};
+
+/// Check that a function's body doesn't pop more values than its
+/// declared inputs provide.
+///
+/// This simulates the depth of the stack through the body: literals,
+/// blocks, lists and maps each push one value, and a call to one of
+/// the primitives pops and pushes according to its stack effect. The
+/// check is only as good as that simulation, so as soon as it reaches
+/// anything whose effect it doesn't know - a call to some other
+/// function, a method call, a cond or a loop - it gives up and
+/// reports success.
+pub fn check_arity(
+    func: &FunctionDecl,
+    prims: &HashMap<String, StackEffect>,
+) -> Result<(), Diagnostic> {
+    let mut depth = func.signature.before.stack.len();
+    for e in &func.body {
+        match e {
+            Expr::IntLit(_, _)
+            | Expr::FloatLit(_, _)
+            | Expr::StringLit(_, _)
+            | Expr::CharLit(_, _)
+            | Expr::BoolLit(_, _)
+            | Expr::Block(_)
+            | Expr::List(_)
+            | Expr::Map(_)
+            | Expr::LocalRef(_) => depth += 1,
+            Expr::FunCall(call) => {
+                let Identifier::Simple(name) = &call.id else {
+                    return Ok(());
+                };
+                let Some(effect) = prims.get(&name.0) else {
+                    return Ok(());
+                };
+                let needed = effect.before.stack.len();
+                if depth < needed {
+                    return Err(Diagnostic::error(
+                        call.span,
+                        format!(
+                            "'{}' needs {} values on the stack, but only {} are available",
+                            name.0, needed, depth
+                        ),
+                    ));
+                }
+                depth = depth - needed + effect.after.stack.len();
+            }
+            _ => return Ok(()),
+        }
+    }
+    return Ok(());
+}
+
+fn collect_image_contexts(image: &StackImage, bound: &mut HashSet<String>) {
+    bound.insert(image.stack_var.0.clone());
+    for t in &image.stack {
+        collect_type_contexts(t, bound);
+    }
+}
+
+fn collect_type_contexts(t: &SType, bound: &mut HashSet<String>) {
+    match t {
+        SType::Function(effect) => {
+            collect_image_contexts(&effect.before, bound);
+            collect_image_contexts(&effect.after, bound);
+        }
+        SType::Parametric(params, _) => {
+            for p in params {
+                collect_type_contexts(p, bound);
+            }
+        }
+        SType::Simple(_) | SType::TypeVar(_) => (),
+    }
+}
+
+/// Check that the context variables of a stack effect are used
+/// consistently.
+///
+/// The before side of an effect binds its own context variable, and
+/// every context variable that appears in the function types on its
+/// stack: in "( @A ( @A -- @B ) -- @B )", calling the function
+/// argument is what produces the @B context. The after side can only
+/// use contexts that were bound that way, so "( @A int -- @C )" is an
+/// error, since nothing says what @C is. Function types on the after
+/// side are checked the same way, with the outer effect's contexts
+/// also in scope. The error points at the effect that uses the
+/// unbound context.
+pub fn check_contexts(effect: &StackEffect) -> Result<(), Diagnostic> {
+    return check_contexts_in(effect, &HashSet::new());
+}
+
+fn check_contexts_in(effect: &StackEffect, outer: &HashSet<String>) -> Result<(), Diagnostic> {
+    let mut bound = outer.clone();
+    collect_image_contexts(&effect.before, &mut bound);
+    let context = &effect.after.stack_var.0;
+    if !bound.contains(context) {
+        return Err(Diagnostic::error(
+            effect.span,
+            format!(
+                "context {} in the result of a stack effect isn't bound by its inputs",
+                context
+            ),
+        ));
+    }
+    for t in &effect.after.stack {
+        check_type_contexts(t, &bound)?;
+    }
+    return Ok(());
+}
+
+fn check_type_contexts(t: &SType, bound: &HashSet<String>) -> Result<(), Diagnostic> {
+    match t {
+        SType::Function(effect) => check_contexts_in(effect, bound)?,
+        SType::Parametric(params, _) => {
+            for p in params {
+                check_type_contexts(p, bound)?;
+            }
+        }
+        SType::Simple(_) | SType::TypeVar(_) => (),
+    }
+    return Ok(());
+}
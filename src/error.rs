@@ -1,13 +1,249 @@
-#[derive(Debug)]
+use crate::lex::{Scanner, Tok};
+use crate::twist::Twist;
+use lalrpop_util::ParseError;
+
+#[derive(Debug, Clone)]
 pub enum Error {
     LexicalError {
+        source: String,
         line: usize,
         column: usize,
+        offset: usize,
         message: String,
     },
     ParseError {
+        source: String,
         line: usize,
         column: usize,
+        offset: usize,
         message: String,
+        expected: Vec<String>,
     },
 }
+
+impl Error {
+    fn location(&self) -> (usize, usize, usize) {
+        return match self {
+            Self::LexicalError {
+                line,
+                column,
+                offset,
+                ..
+            }
+            | Self::ParseError {
+                line,
+                column,
+                offset,
+                ..
+            } => (*line, *column, *offset),
+        };
+    }
+
+    /// The byte offset in the source where the error occurred.
+    pub fn offset(&self) -> usize {
+        return self.location().2;
+    }
+
+    /// The id of the source that the error occurred in - normally
+    /// the name of the file that the scanner was reading.
+    pub fn source(&self) -> &str {
+        return match self {
+            Self::LexicalError { source, .. } | Self::ParseError { source, .. } => source,
+        };
+    }
+
+    fn message(&self) -> &str {
+        return match self {
+            Self::LexicalError { message, .. } | Self::ParseError { message, .. } => message,
+        };
+    }
+
+    /// Render the error in the style of rustc: the message, followed by
+    /// the source line that the error occurred in, with a caret
+    /// pointing at the error's position. If "color" is set, the output
+    /// includes ANSI escapes to highlight the error.
+    pub fn render_pretty(&self, text: &str, color: bool) -> String {
+        let (line, column, offset) = self.location();
+        let offset = offset.min(text.len());
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+        let source_line = text[line_start..line_end].trim_end_matches('\r');
+
+        // Copy tabs from the source line into the caret line, so that the
+        // caret lines up however the terminal expands them.
+        let caret_indent: String = text[line_start..offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(line.to_string().len());
+        let (red, blue, reset) = if color {
+            ("\x1b[1;31m", "\x1b[1;34m", "\x1b[0m")
+        } else {
+            ("", "", "")
+        };
+
+        let mut rendered = String::new();
+        rendered.push_str(&format!("{}error{}: {}\n", red, reset, self.message()));
+        rendered.push_str(&format!(
+            "{}{}-->{} {}:{}:{}\n",
+            gutter,
+            blue,
+            reset,
+            self.source(),
+            line,
+            column
+        ));
+        rendered.push_str(&format!("{}{} |{}\n", gutter, blue, reset));
+        rendered.push_str(&format!("{}{} |{} {}\n", blue, line, reset, source_line));
+        rendered.push_str(&format!(
+            "{}{} |{} {}{}^{}\n",
+            gutter, blue, reset, caret_indent, red, reset
+        ));
+        return rendered;
+    }
+
+    /// Render the error as a JSON object, for tools like editors that
+    /// want to read errors rather than show them. The object has
+    /// "severity", "message", "file", "line" and "column" fields, and
+    /// parse errors also have an "expected" field with the list of
+    /// tokens that the parser would have accepted.
+    pub fn to_json(&self) -> String {
+        let (line, column, _) = self.location();
+        let mut rendered = String::new();
+        rendered.push_str("{\"severity\": \"error\", \"message\": ");
+        Twist::json_string(&mut rendered, self.message());
+        rendered.push_str(", \"file\": ");
+        Twist::json_string(&mut rendered, self.source());
+        rendered.push_str(&format!(", \"line\": {}, \"column\": {}", line, column));
+        if let Self::ParseError { expected, .. } = self {
+            rendered.push_str(", \"expected\": [");
+            for (i, e) in expected.iter().enumerate() {
+                if i > 0 {
+                    rendered.push_str(", ");
+                }
+                Twist::json_string(&mut rendered, e);
+            }
+            rendered.push(']');
+        }
+        rendered.push('}');
+        return rendered;
+    }
+}
+
+/// The number of single-character edits needed to turn one string
+/// into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diag
+            } else {
+                1 + diag.min(above).min(row[j])
+            };
+            diag = above;
+        }
+    }
+    return row[b.len()];
+}
+
+/// If a symbol looks like a misspelling of one of the keywords that
+/// the parser was expecting, find the closest one.
+fn suggest_keyword(sym: &str, expected: &[String]) -> Option<String> {
+    return expected
+        .iter()
+        .filter_map(|e| e.strip_prefix('"').and_then(|e| e.strip_suffix('"')))
+        .filter(|e| e.chars().all(|c| c.is_alphabetic()))
+        .map(|e| (edit_distance(sym, e), e))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, e)| e.to_string());
+}
+
+/// Turn lalrpop's list of expected terminals into something fit for
+/// people to read. Lalrpop quotes the terminals that are written as
+/// strings in the grammar, like "\"end\"", and uses the bare names of
+/// the others, like "INTLIT"; the quotes are dropped from the first
+/// kind, and the second kind are replaced by a description. The
+/// result is sorted, with duplicates removed, so that messages built
+/// from it don't depend on the order of lalrpop's tables.
+pub fn describe_expected(expected: &[String]) -> Vec<String> {
+    let mut described: Vec<String> = expected
+        .iter()
+        .map(|e| match e.as_str() {
+            "SYMBOL" => "symbol".to_string(),
+            "STACKVAR" => "stack variable".to_string(),
+            "TYPEVAR" => "type variable".to_string(),
+            "INTLIT" => "integer literal".to_string(),
+            "FLOATLIT" => "float literal".to_string(),
+            "STRINGLIT" => "string literal".to_string(),
+            "CHARLIT" => "char literal".to_string(),
+            _ => e.trim_matches('"').to_string(),
+        })
+        .collect();
+    described.sort();
+    described.dedup();
+    return described;
+}
+
+impl Error {
+    /// Convert an error from the parser into an Error, using the
+    /// source text to work out the line and column of the error.
+    pub fn from_parse_error(source: &str, text: &str, err: ParseError<usize, Tok, Error>) -> Error {
+        let (offset, message, expected) = match err {
+            ParseError::User { error } => return error,
+            ParseError::InvalidToken { location } => {
+                (location, "Invalid token".to_string(), Vec::new())
+            }
+            ParseError::UnrecognizedEof { location, expected } => {
+                let described = describe_expected(&expected);
+                (
+                    location,
+                    format!(
+                        "Unexpected end of input; expected one of {}",
+                        described.join(", ")
+                    ),
+                    described,
+                )
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, tok, _),
+                expected,
+            } => {
+                let described = describe_expected(&expected);
+                let mut message = format!(
+                    "Unexpected token '{}'; expected one of {}",
+                    tok,
+                    described.join(", ")
+                );
+                if let Tok::SYMBOL(sym) = &tok {
+                    if let Some(kw) = suggest_keyword(sym, &expected) {
+                        message.push_str(&format!("; did you mean '{}'?", kw));
+                    }
+                }
+                (start, message, described)
+            }
+            ParseError::ExtraToken {
+                token: (start, tok, _),
+            } => (
+                start,
+                format!("Unexpected extra token '{}'", tok),
+                Vec::new(),
+            ),
+        };
+        let offset = offset.min(text.len());
+        let (line, column) = Scanner::new(source.to_string(), text).line_and_col(offset);
+        return Error::ParseError {
+            source: source.to_string(),
+            line,
+            column,
+            offset,
+            message,
+            expected,
+        };
+    }
+}
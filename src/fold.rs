@@ -0,0 +1,57 @@
+use crate::ast::{Expr, Identifier, Span};
+use crate::diagnostic::Diagnostic;
+
+fn fold_op(op: &str, a: i64, b: i64) -> Option<i64> {
+    return match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" => a.checked_div(b),
+        _ => None,
+    };
+}
+
+/// Fold integer arithmetic on literals in a body: a sequence like
+/// "3 4 +" is replaced by the single literal "7". Folding repeats
+/// until nothing more can be folded, so "1 2 + 3 *" becomes "9".
+///
+/// Anything that would fail at runtime - dividing by zero, or an
+/// overflow - is left alone, so that it fails there instead. A
+/// division by zero is certain to fail, so it's also reported as a
+/// warning.
+pub fn fold_constants(body: &mut Vec<Expr>) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i + 2 < body.len() {
+        let folded = match (&body[i], &body[i + 1], &body[i + 2]) {
+            (Expr::IntLit(a, span), Expr::IntLit(b, _), Expr::FunCall(call))
+                if call.type_args.is_none() =>
+            {
+                match &call.id {
+                    Identifier::Simple(op) => {
+                        let span = Span::new(span.start, call.span.end);
+                        if op.0 == "/" && *b == 0 {
+                            result.push(Diagnostic::warning(
+                                span,
+                                format!("{} is divided by zero", a),
+                            ));
+                        }
+                        fold_op(&op.0, *a, *b).map(|v| Expr::IntLit(v, span))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match folded {
+            Some(lit) => {
+                body.splice(i..i + 3, [lit]);
+                // The new literal may be the second operand of an
+                // operation that starts just before it.
+                i = i.saturating_sub(1);
+            }
+            None => i += 1,
+        }
+    }
+    return result;
+}
@@ -0,0 +1,421 @@
+use crate::ast::{
+    AliasDecl, BlockExpr, CondExpr, ConstDecl, Decl, Expr, FunCallExpr, FunctionDecl, Identifier,
+    ListExpr, LoopExpr, MapExpr, MethodDecl, SType, Sect, StackEffect, StackImage, StructDecl,
+    Symbol, TypeParam, UseDecl, VarDecl,
+};
+
+/// Regenerate Schism source code from the AST.
+///
+/// Unlike [crate::ast::Renderable], which produces a dump for
+/// debugging, this produces code in the concrete syntax that the
+/// parser accepts, laid out in a canonical way: each expression in a
+/// body goes on its own line, and nested bodies are indented by one
+/// level. Parsing the output and formatting it again gives back the
+/// same text. Comments aren't part of the AST, so they're lost.
+pub trait ToSource {
+    fn source_into(&self, target: &mut String, indent: usize);
+
+    fn to_source(&self) -> String {
+        let mut s = String::new();
+        self.source_into(&mut s, 0);
+        return s;
+    }
+}
+
+fn indent(target: &mut String, ind: usize) {
+    target.push_str(&"   ".repeat(ind))
+}
+
+fn body_into(target: &mut String, body: &[Expr], ind: usize) {
+    for e in body {
+        indent(target, ind);
+        e.source_into(target, ind);
+        target.push('\n');
+    }
+}
+
+/// Write a sequence of expressions on a single line, as they appear
+/// in list and map literals.
+fn inline_into(target: &mut String, exprs: &[Expr], ind: usize) {
+    for (i, e) in exprs.iter().enumerate() {
+        if i > 0 {
+            target.push(' ');
+        }
+        e.source_into(target, ind);
+    }
+}
+
+fn comma_list<T: ToSource>(items: &[T]) -> String {
+    return items
+        .iter()
+        .map(|i| i.to_source())
+        .collect::<Vec<String>>()
+        .join(", ");
+}
+
+/// Write a bracketed list of type arguments. A "[" that's directly
+/// followed by another one would scan as "[[", which opens a block,
+/// so there's a space between them when the first argument is itself
+/// parametric.
+fn type_args_into(target: &mut String, args: &[SType]) {
+    let items = comma_list(args);
+    target.push('[');
+    if items.starts_with('[') {
+        target.push(' ');
+    }
+    target.push_str(&items);
+    target.push(']');
+}
+
+fn type_params_into(target: &mut String, type_params: &Option<Vec<TypeParam>>) {
+    if let Some(tps) = type_params {
+        target.push('[');
+        target.push_str(&comma_list(tps));
+        target.push_str("] ");
+    }
+}
+
+fn escape_char(c: char, quote: char) -> String {
+    return match c {
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\t' => "\\t".to_string(),
+        '\\' => "\\\\".to_string(),
+        c if c == quote => format!("\\{}", c),
+        c if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        c => c.to_string(),
+    };
+}
+
+impl ToSource for Sect {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        for u in &self.uses {
+            u.source_into(target, ind);
+        }
+        for (i, d) in self.decls.iter().enumerate() {
+            if i > 0 || !self.uses.is_empty() {
+                target.push('\n');
+            }
+            d.source_into(target, ind);
+        }
+    }
+}
+
+impl ToSource for UseDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("use ");
+        target.push_str(&self.sect.to_source());
+        if let Some(names) = &self.names {
+            target.push_str(" { ");
+            target.push_str(&comma_list(names));
+            target.push_str(" }");
+        }
+        target.push('\n');
+    }
+}
+
+impl ToSource for Symbol {
+    fn source_into(&self, target: &mut String, _: usize) {
+        target.push_str(&self.0)
+    }
+}
+
+impl ToSource for Identifier {
+    fn source_into(&self, target: &mut String, _: usize) {
+        match self {
+            Self::Qualified(syms) => target.push_str(
+                &syms
+                    .iter()
+                    .map(|s| s.0.clone())
+                    .collect::<Vec<String>>()
+                    .join("::"),
+            ),
+            Self::Simple(s) => target.push_str(&s.0),
+            Self::System(s) => target.push_str(s),
+        }
+    }
+}
+
+impl ToSource for Decl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        match self {
+            Self::Struct(s) => s.source_into(target, ind),
+            Self::Function(f) => f.source_into(target, ind),
+            Self::Var(v) => v.source_into(target, ind),
+            Self::Alias(a) => a.source_into(target, ind),
+            Self::Const(c) => c.source_into(target, ind),
+        }
+    }
+}
+
+impl ToSource for TypeParam {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str(&self.name.0);
+        if let Some(c) = &self.constraint {
+            target.push_str(" << ");
+            c.source_into(target, ind);
+        }
+    }
+}
+
+impl ToSource for StructDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("struct ");
+        type_params_into(target, &self.type_params);
+        target.push_str(&self.name.0);
+        if let Some(supers) = &self.supers {
+            target.push_str(" ( ");
+            target.push_str(&comma_list(supers));
+            target.push_str(" )");
+        }
+        target.push_str(" is\n");
+        for f in &self.fields {
+            indent(target, ind + 1);
+            target.push_str("slot ");
+            target.push_str(&f.name.0);
+            target.push_str(": ");
+            f.s_type.source_into(target, ind + 1);
+            target.push('\n');
+        }
+        for m in &self.methods {
+            m.source_into(target, ind + 1);
+        }
+        indent(target, ind);
+        target.push_str("end\n");
+    }
+}
+
+impl ToSource for MethodDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("meth ");
+        target.push_str(&self.name.0);
+        target.push(' ');
+        self.effect.source_into(target, ind);
+        target.push_str(" is\n");
+        body_into(target, &self.body, ind + 1);
+        indent(target, ind);
+        target.push_str("end\n");
+    }
+}
+
+impl ToSource for FunctionDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("fun ");
+        type_params_into(target, &self.type_params);
+        target.push_str(&self.name.0);
+        target.push(' ');
+        self.signature.source_into(target, ind);
+        target.push_str(" is\n");
+        body_into(target, &self.body, ind + 1);
+        indent(target, ind);
+        target.push_str("end\n");
+    }
+}
+
+impl ToSource for VarDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("var ");
+        target.push_str(&self.name.0);
+        target.push_str(": ");
+        self.s_type.source_into(target, ind);
+        target.push_str(" init\n");
+        body_into(target, &self.init_value, ind + 1);
+        indent(target, ind);
+        target.push_str("end\n");
+    }
+}
+
+impl ToSource for ConstDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("const ");
+        target.push_str(&self.name.0);
+        target.push_str(": ");
+        self.value_type.source_into(target, ind);
+        target.push_str(" is\n");
+        body_into(target, &self.body, ind + 1);
+        indent(target, ind);
+        target.push_str("end\n");
+    }
+}
+
+impl ToSource for AliasDecl {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        indent(target, ind);
+        target.push_str("alias ");
+        type_params_into(target, &self.type_params);
+        target.push_str(&self.name.0);
+        target.push_str(" is ");
+        self.aliased.source_into(target, ind);
+        target.push_str(" end\n");
+    }
+}
+
+impl ToSource for SType {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        match self {
+            Self::Simple(id) => id.source_into(target, ind),
+            Self::Parametric(params, id) => {
+                type_args_into(target, params);
+                id.source_into(target, ind);
+            }
+            Self::Function(effect) => effect.source_into(target, ind),
+            Self::TypeVar(t) => target.push_str(&t.0),
+        }
+    }
+}
+
+impl ToSource for StackImage {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        // Context variables invented by the parser ("@_0" and so on)
+        // can't be written in source; leaving them out makes the
+        // parser invent a fresh one again.
+        if !self.stack_var.0.starts_with("@_") {
+            target.push_str(&self.stack_var.0);
+            target.push(' ');
+        }
+        if let Some(name) = &self.result_name {
+            target.push_str(&name.0);
+            target.push_str(" -> ");
+        }
+        for t in &self.stack {
+            t.source_into(target, ind);
+            target.push(' ');
+        }
+    }
+}
+
+impl ToSource for StackEffect {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str("( ");
+        self.before.source_into(target, ind);
+        target.push_str("-- ");
+        self.after.source_into(target, ind);
+        target.push(')');
+        if !self.effect_domains.is_empty() {
+            target.push_str(" uses ");
+            target.push_str(&comma_list(&self.effect_domains));
+        }
+    }
+}
+
+impl ToSource for Expr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        match self {
+            Self::FunCall(f) => f.source_into(target, ind),
+            Self::List(l) => l.source_into(target, ind),
+            Self::Map(m) => m.source_into(target, ind),
+            Self::Cond(c) => c.source_into(target, ind),
+            Self::Loop(l) => l.source_into(target, ind),
+            Self::MethodCall(m) => {
+                target.push_str("<- ");
+                target.push_str(&m.sym.0);
+            }
+            Self::Block(b) => b.source_into(target, ind),
+            Self::IntLit(i, _) => target.push_str(&i.to_string()),
+            Self::FloatLit(f, _) => target.push_str(f),
+            Self::StringLit(s, _) => {
+                target.push('"');
+                for c in s.chars() {
+                    target.push_str(&escape_char(c, '"'));
+                }
+                target.push('"');
+            }
+            Self::CharLit(c, _) => {
+                target.push('\'');
+                target.push_str(&escape_char(*c, '\''));
+                target.push('\'');
+            }
+            Self::BoolLit(b, _) => target.push_str(&b.to_string()),
+            Self::Local(l) => {
+                target.push_str("local ");
+                target.push_str(&l.name.0);
+            }
+            Self::LocalRef(l) => target.push_str(&l.name.0),
+        }
+    }
+}
+
+impl ToSource for FunCallExpr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        if let Some(tas) = &self.type_args {
+            type_args_into(target, tas);
+        }
+        self.id.source_into(target, ind);
+    }
+}
+
+impl ToSource for ListExpr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str("#[ ");
+        self.value_type.source_into(target, ind);
+        target.push_str(" | ");
+        for (i, v) in self.values.iter().enumerate() {
+            if i > 0 {
+                target.push_str(", ");
+            }
+            inline_into(target, v, ind);
+        }
+        target.push_str(" ]#");
+    }
+}
+
+impl ToSource for MapExpr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str("#{ ");
+        self.key_type.source_into(target, ind);
+        target.push_str(", ");
+        self.value_type.source_into(target, ind);
+        target.push_str(" | ");
+        for (i, (k, v)) in self.values.iter().enumerate() {
+            if i > 0 {
+                target.push_str(", ");
+            }
+            target.push('(');
+            inline_into(target, k, ind);
+            target.push_str(", ");
+            inline_into(target, v, ind);
+            target.push(')');
+        }
+        target.push_str(" }#");
+    }
+}
+
+impl ToSource for CondExpr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str("if\n");
+        body_into(target, &self.true_block, ind + 1);
+        if !self.false_block.is_empty() {
+            indent(target, ind);
+            target.push_str("else\n");
+            body_into(target, &self.false_block, ind + 1);
+        }
+        indent(target, ind);
+        target.push_str("end");
+    }
+}
+
+impl ToSource for LoopExpr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str("loop\n");
+        body_into(target, &self.body, ind + 1);
+        indent(target, ind);
+        target.push_str("end");
+    }
+}
+
+impl ToSource for BlockExpr {
+    fn source_into(&self, target: &mut String, ind: usize) {
+        target.push_str("[[ ");
+        self.effect.source_into(target, ind);
+        target.push('\n');
+        body_into(target, &self.body, ind + 1);
+        indent(target, ind);
+        target.push_str("]]");
+    }
+}
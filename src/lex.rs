@@ -1,6 +1,6 @@
 use crate::error::Error;
 use line_col::LineColLookup;
-use std::{collections::HashMap, str::CharIndices};
+use std::{collections::HashMap, fmt, str::CharIndices};
 use unicode_categories::UnicodeCategories;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -9,11 +9,13 @@ pub enum Tok {
     STACKVAR(String), //  @alpha+
     TYPEVAR(String),  //   'alpha+
     INTLIT(i64),
-    FLOATLIT(f64),
+    FLOATLIT(String), // the literal's text as written, less any '_' separators
     STRINGLIT(String),
     CHARLIT(char),
+    DOCCOMMENT(String), // /// text
     // Keywords
     USE,
+    USES,
     STRUCT,
     END,
     SLOT,
@@ -25,11 +27,17 @@ pub enum Tok {
     IF,
     ELSE,
     LOOP,
+    ALIAS,
+    CONST,
+    LOCAL,
+    TRUE,
+    FALSE,
 
     // symbols
     BAR,     // |
     SUBTYPE, // <<
     SEND,    // <-
+    ARROW,   // ->, reserved for naming results in stack effects
 
     LBRACE,   // {
     RBRACE,   //  }
@@ -48,6 +56,104 @@ pub enum Tok {
     COLON,    // :
     COCO,     // ::
     COMMA,
+
+    // Only produced by scanners built with [Scanner::with_eof].
+    EOF,
+}
+
+/// Tokens display as they're spelled in source, so that they can
+/// be quoted back to the user in error messages.
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let spelling = match self {
+            Tok::SYMBOL(s) | Tok::STACKVAR(s) | Tok::TYPEVAR(s) | Tok::FLOATLIT(s) => {
+                return f.write_str(s)
+            }
+            Tok::INTLIT(i) => return write!(f, "{}", i),
+            Tok::STRINGLIT(s) => return write!(f, "{:?}", s),
+            Tok::CHARLIT(c) => return write!(f, "{:?}", c),
+            Tok::DOCCOMMENT(s) => return write!(f, "///{}", s),
+            Tok::USE => "use",
+            Tok::USES => "uses",
+            Tok::STRUCT => "struct",
+            Tok::END => "end",
+            Tok::SLOT => "slot",
+            Tok::METH => "meth",
+            Tok::FUN => "fun",
+            Tok::IS => "is",
+            Tok::VAR => "var",
+            Tok::INIT => "init",
+            Tok::IF => "if",
+            Tok::ELSE => "else",
+            Tok::LOOP => "loop",
+            Tok::ALIAS => "alias",
+            Tok::CONST => "const",
+            Tok::LOCAL => "local",
+            Tok::TRUE => "true",
+            Tok::FALSE => "false",
+            Tok::BAR => "|",
+            Tok::SUBTYPE => "<<",
+            Tok::SEND => "<-",
+            Tok::ARROW => "->",
+            Tok::LBRACE => "{",
+            Tok::RBRACE => "}",
+            Tok::LBRACK => "[",
+            Tok::RBRACK => "]",
+            Tok::POUND => "#",
+            Tok::PLBRACK => "#[",
+            Tok::PRBRACK => "]#",
+            Tok::PLBRACE => "#{",
+            Tok::PRBRACE => "}#",
+            Tok::BLOPEN => "[[",
+            Tok::BLCLOSE => "]]",
+            Tok::LPAREN => "(",
+            Tok::RPAREN => ")",
+            Tok::DASHDASH => "--",
+            Tok::COLON => ":",
+            Tok::COCO => "::",
+            Tok::COMMA => ",",
+            Tok::EOF => "end of input",
+        };
+        return f.write_str(spelling);
+    }
+}
+
+/// A token whose text borrows from the scanner's input, for tools that
+/// only need the token stream, and not a parse.
+///
+/// Scanning a symbol or a variable this way doesn't allocate. Every
+/// other kind of token is an ordinary [Tok].
+#[derive(Debug, PartialEq, Clone)]
+pub enum BorrowedTok<'input> {
+    SYMBOL(&'input str),
+    STACKVAR(&'input str),
+    TYPEVAR(&'input str),
+    TOKEN(Tok),
+}
+
+impl<'input> BorrowedTok<'input> {
+    /// Convert to the owned token that the parser uses.
+    pub fn into_tok(self) -> Tok {
+        match self {
+            BorrowedTok::SYMBOL(s) => return Tok::SYMBOL(s.to_string()),
+            BorrowedTok::STACKVAR(s) => return Tok::STACKVAR(s.to_string()),
+            BorrowedTok::TYPEVAR(s) => return Tok::TYPEVAR(s.to_string()),
+            BorrowedTok::TOKEN(t) => return t,
+        }
+    }
+}
+
+/// If a token opens a bracketed construct, get the token that closes it.
+fn closing_delimiter(tok: &Tok) -> Option<Tok> {
+    return match tok {
+        Tok::LPAREN => Some(Tok::RPAREN),
+        Tok::LBRACK => Some(Tok::RBRACK),
+        Tok::LBRACE => Some(Tok::RBRACE),
+        Tok::PLBRACK => Some(Tok::PRBRACK),
+        Tok::PLBRACE => Some(Tok::PRBRACE),
+        Tok::BLOPEN => Some(Tok::BLCLOSE),
+        _ => None,
+    };
 }
 
 /// An extension trait providing tests of a couple of
@@ -58,14 +164,25 @@ trait CharacterCategories {
     fn is_syntax_char(&self) -> bool;
 }
 
+// The Unicode category lookups are slow, and most source is ASCII.
+// Every printable ASCII character that isn't a letter or a digit is
+// either punctuation or a symbol, so for ASCII, the checks below
+// reduce to whether the character is printable and, for the start
+// of an identifier, not a digit.
 impl CharacterCategories for char {
     fn is_id_start_char(&self) -> bool {
+        if self.is_ascii() {
+            return self.is_ascii_graphic() && !self.is_ascii_digit() && !self.is_syntax_char();
+        }
         return !self.is_syntax_char()
             && !self.is_whitespace()
             && (self.is_alphabetic() || self.is_symbol() || self.is_punctuation());
     }
 
     fn is_id_char(&self) -> bool {
+        if self.is_ascii() {
+            return self.is_ascii_graphic() && !self.is_syntax_char();
+        }
         return !self.is_syntax_char()
             && !self.is_whitespace()
             && (self.is_alphabetic()
@@ -82,6 +199,16 @@ impl CharacterCategories for char {
     }
 }
 
+/// Get the value of the text of a float literal.
+///
+/// Float tokens carry their text as it was written, minus any digit
+/// separators, so that tools like formatters can reproduce it; this
+/// converts that text to a number, returning None if it isn't a
+/// well-formed float.
+pub fn float_value(text: &str) -> Option<f64> {
+    return text.replace('_', "").parse::<f64>().ok();
+}
+
 pub struct Scanner<'input> {
     source_id: String,
     index: LineColLookup<'input>,
@@ -90,6 +217,12 @@ pub struct Scanner<'input> {
     current: Option<(usize, char)>,
     next: Option<(usize, char)>,
     reserved: HashMap<String, Tok>,
+    emit_eof: bool,
+    eof_emitted: bool,
+    emit_doc_comments: bool,
+    lenient: bool,
+    peeked: Option<Option<ScannerResult<'input>>>,
+    tab_width: usize,
 }
 
 impl<'input> Scanner<'input> {
@@ -101,8 +234,12 @@ impl<'input> Scanner<'input> {
             current: None,
             next: None,
             input,
+            // Only these words and operators are reserved. Any other run of
+            // symbol characters, like "!" or "set!", is an ordinary symbol,
+            // whether or not it stands alone.
             reserved: HashMap::from([
                 ("use".to_string(), Tok::USE),
+                ("uses".to_string(), Tok::USES),
                 ("struct".to_string(), Tok::STRUCT),
                 ("end".to_string(), Tok::END),
                 ("slot".to_string(), Tok::SLOT),
@@ -114,24 +251,198 @@ impl<'input> Scanner<'input> {
                 ("if".to_string(), Tok::IF),
                 ("else".to_string(), Tok::ELSE),
                 ("loop".to_string(), Tok::LOOP),
+                ("alias".to_string(), Tok::ALIAS),
+                ("const".to_string(), Tok::CONST),
+                ("local".to_string(), Tok::LOCAL),
+                ("true".to_string(), Tok::TRUE),
+                ("false".to_string(), Tok::FALSE),
                 ("|".to_string(), Tok::BAR),
                 ("<<".to_string(), Tok::SUBTYPE),
                 ("<-".to_string(), Tok::SEND),
+                ("->".to_string(), Tok::ARROW),
                 ("--".to_string(), Tok::DASHDASH),
             ]),
+            emit_eof: false,
+            eof_emitted: false,
+            emit_doc_comments: false,
+            lenient: false,
+            peeked: None,
+            tab_width: 8,
         };
         scanner.advance();
         return scanner;
     }
 
+    /// Create a scanner that recovers from lexical errors.
+    ///
+    /// When iterating, a lenient scanner moves past the offending
+    /// character after reporting an error and carries on, so the caller
+    /// can collect every error in the input along with the valid tokens
+    /// between them.
+    pub fn new_lenient(id: String, input: &'input str) -> Scanner<'input> {
+        let mut scanner = Scanner::new(id, input);
+        scanner.lenient = true;
+        return scanner;
+    }
+
+    /// Make the scanner produce a single [Tok::EOF] token at the end
+    /// of the input, before it starts returning None.
+    ///
+    /// This is for tools that want an explicit end marker; the parser
+    /// relies on None, so don't use it for scanners fed to lalrpop.
+    pub fn with_eof(mut self) -> Scanner<'input> {
+        self.emit_eof = true;
+        return self;
+    }
+
+    /// Make the scanner produce [Tok::DOCCOMMENT] tokens for "///"
+    /// comments. By default, they're skipped like any other comment.
+    ///
+    /// The grammar doesn't accept doc comments, so this is for tools
+    /// that work with the token stream; don't use it for scanners fed
+    /// to lalrpop.
+    pub fn with_doc_comments(mut self) -> Scanner<'input> {
+        self.emit_doc_comments = true;
+        return self;
+    }
+
+    /// Set the number of columns that a tab advances to when
+    /// reporting locations. The default is 8.
+    pub fn with_tab_width(mut self, width: usize) -> Scanner<'input> {
+        self.tab_width = width;
+        return self;
+    }
+
     /// Convert a position within the input string to
     /// a (line, column) pair.
     ///
+    /// Columns are visual: each character counts as one column,
+    /// except for tabs, which advance to the next tab stop.
+    ///
     /// Note that this assumes that the position was returned
     /// by the scanner as the location of a token. It will panic
     /// if you give it an index beyond the end of the input.
     pub fn line_and_col(&self, pos: usize) -> (usize, usize) {
-        self.index.get(pos)
+        let (line, byte_col) = self.index.get(pos);
+        let line_start = pos - (byte_col - 1);
+        let mut column = 0;
+        for c in self.input[line_start..pos].chars() {
+            if c == '\t' && self.tab_width > 0 {
+                column = (column / self.tab_width + 1) * self.tab_width;
+            } else {
+                column = column + 1;
+            }
+        }
+        return (line, column + 1);
+    }
+
+    /// Get the exact source text of a token, given the start and end
+    /// locations that the scanner returned with it.
+    ///
+    /// Token locations are byte offsets into the input, not (line, column)
+    /// pairs - use [Scanner::line_and_col] to convert them for display.
+    /// The lexeme includes everything that produced the token, like the
+    /// quotes around a string literal or the sign of a number.
+    pub fn lexeme(&self, start: usize, end: usize) -> &'input str {
+        &self.input[start..end]
+    }
+
+    /// Make sure that scanning gets past a lexical error at pos. Most
+    /// errors have already consumed the bad character, and then this
+    /// does nothing, so that a valid token right after it isn't lost.
+    fn recover(&mut self, pos: usize) {
+        while let Some((i, _)) = self.current {
+            if i > pos {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    /// Build a lexical error located at the byte offset pos.
+    fn lexical_error(&self, pos: usize, message: String) -> Error {
+        let (line, column) = self.line_and_col(pos);
+        return Error::LexicalError {
+            source: self.source_id.clone(),
+            line,
+            column,
+            offset: pos,
+            message,
+        };
+    }
+
+    /// Check that the brackets, braces and parens in the input are
+    /// properly nested, without parsing it.
+    ///
+    /// The parser reports a misplaced closing delimiter as just an
+    /// unexpected token, which doesn't help much when the real mistake
+    /// was the opener, lines earlier. This scans the input and reports
+    /// the first closer that doesn't match the innermost open
+    /// delimiter, naming where that delimiter was opened, or the
+    /// innermost delimiter that's never closed at all.
+    pub fn check_delimiters(mut self) -> Result<(), Error> {
+        let mut open: Vec<(usize, Tok)> = Vec::new();
+        while let Some(result) = self.scan_token() {
+            let (start, tok, _) = result?;
+            if closing_delimiter(&tok).is_some() {
+                open.push((start, tok));
+                continue;
+            }
+            if !matches!(
+                tok,
+                Tok::RPAREN
+                    | Tok::RBRACK
+                    | Tok::RBRACE
+                    | Tok::PRBRACK
+                    | Tok::PRBRACE
+                    | Tok::BLCLOSE
+            ) {
+                continue;
+            }
+            match open.pop() {
+                Some((_, opener)) if closing_delimiter(&opener) == Some(tok.clone()) => (),
+                Some((pos, opener)) => {
+                    let expected = closing_delimiter(&opener).unwrap();
+                    let (line, column) = self.line_and_col(pos);
+                    return Err(self.delimiter_error(
+                        start,
+                        format!(
+                            "Mismatched '{}': expected '{}' to close the '{}' at line {}, column {}",
+                            tok, expected, opener, line, column
+                        ),
+                        Some(expected),
+                    ));
+                }
+                None => {
+                    return Err(self.delimiter_error(
+                        start,
+                        format!("Unexpected '{}' with nothing open to close", tok),
+                        None,
+                    ))
+                }
+            }
+        }
+        if let Some((pos, opener)) = open.pop() {
+            let expected = closing_delimiter(&opener).unwrap();
+            return Err(self.delimiter_error(
+                pos,
+                format!("Unclosed '{}': expected a matching '{}'", opener, expected),
+                Some(expected),
+            ));
+        }
+        return Ok(());
+    }
+
+    fn delimiter_error(&self, pos: usize, message: String, expected: Option<Tok>) -> Error {
+        let (line, column) = self.line_and_col(pos);
+        return Error::ParseError {
+            source: self.source_id.clone(),
+            line,
+            column,
+            offset: pos,
+            message,
+            expected: expected.iter().map(|t| t.to_string()).collect(),
+        };
     }
 
     fn advance(&mut self) {
@@ -145,11 +456,19 @@ impl<'input> Scanner<'input> {
 
 pub type ScannerResult<'input> = Result<(usize, Tok, usize), Error>;
 
+pub type BorrowedResult<'input> = Result<(usize, BorrowedTok<'input>, usize), Error>;
+
 impl<'input> Iterator for Scanner<'input> {
     type Item = ScannerResult<'input>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        return self.scan_token();
+        let result = self.scan_token();
+        if self.lenient {
+            if let Some(Err(e)) = &result {
+                self.recover(e.offset());
+            }
+        }
+        return result;
     }
 }
 
@@ -162,21 +481,88 @@ impl<'input> Iterator for Scanner<'input> {
 ///
 /// So, for example, the scanning process for a float literal:
 /// - Enter the "scan_number" state. Any numeric character stays in
-///    "scan_number". A "." switches to "scan_float"; any non-numeric
-///    is a terminal which returns an integer token.
+///    "scan_number". A "." followed by a digit switches to "scan_float";
+///    anything else is a terminal which returns an integer token.
 /// - In the scan_float state, you consume the ".", and then again
 ///   stay in the state for any numeric character. If you see an "e"
-///   (for exponent), then you switch to "scan_float_exponent".
+///   (for exponent), then you switch to "scan_float_exponent". An "e"
+///   directly after the integer digits also switches to it, so "6e23"
+///   is a float.
 /// - Etc.
 ///
 /// Each time that you switch states in the above explanation, you
 /// just call the new state function in the scanner code.
 impl<'input> Scanner<'input> {
+    /// Look at the next token without consuming it. The token is
+    /// cached, and returned by the next call to scan_token.
+    pub fn peek_token(&mut self) -> Option<ScannerResult<'input>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.scan_token());
+        }
+        return self.peeked.clone().unwrap();
+    }
+
     pub fn scan_token(&mut self) -> Option<ScannerResult<'input>> {
+        if let Some(peeked) = self.peeked.take() {
+            return peeked;
+        }
+        match self.scan_unnamed_token() {
+            Some(Ok((start, tok, end))) => {
+                return Some(Ok((start, self.named(tok, start, end), end)))
+            }
+            other => return other,
+        }
+    }
+
+    /// Scan the next token, borrowing the text of symbols and variables
+    /// from the input instead of copying it.
+    ///
+    /// This is for tools that scan a lot of source without parsing it.
+    /// It can be freely mixed with scan_token and peek_token.
+    pub fn scan_borrowed_token(&mut self) -> Option<BorrowedResult<'input>> {
+        let result = match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.scan_unnamed_token(),
+        };
+        match result {
+            Some(Ok((start, tok, end))) => {
+                return Some(Ok((start, self.borrowed(tok, start, end), end)))
+            }
+            Some(Err(e)) => return Some(Err(e)),
+            None => return None,
+        }
+    }
+
+    /// Fill in the text of a symbol or variable token from its span.
+    fn named(&self, tok: Tok, start: usize, end: usize) -> Tok {
+        match tok {
+            Tok::SYMBOL(_) => return Tok::SYMBOL(self.lexeme(start, end).to_string()),
+            Tok::STACKVAR(_) => return Tok::STACKVAR(self.lexeme(start, end).to_string()),
+            Tok::TYPEVAR(_) => return Tok::TYPEVAR(self.lexeme(start, end).to_string()),
+            other => return other,
+        }
+    }
+
+    /// Replace the text of a symbol or variable token with a slice of
+    /// the input.
+    fn borrowed(&self, tok: Tok, start: usize, end: usize) -> BorrowedTok<'input> {
+        match tok {
+            Tok::SYMBOL(_) => return BorrowedTok::SYMBOL(self.lexeme(start, end)),
+            Tok::STACKVAR(_) => return BorrowedTok::STACKVAR(self.lexeme(start, end)),
+            Tok::TYPEVAR(_) => return BorrowedTok::TYPEVAR(self.lexeme(start, end)),
+            other => return BorrowedTok::TOKEN(other),
+        }
+    }
+
+    /// Scan the next token, but leave the text of symbols and variables
+    /// empty. The text of those tokens is always exactly their span
+    /// in the input, and an empty String doesn't allocate, so the
+    /// public scan functions fill it in only when they need to.
+    fn scan_unnamed_token(&mut self) -> Option<ScannerResult<'input>> {
         loop {
             match self.current {
                 // Skip WS
-                Some((_, ' ')) | Some((_, '\n')) | Some((_, '\t')) => {
+                Some((_, ' ')) | Some((_, '\n')) | Some((_, '\t')) | Some((_, '\r')) => {
                     self.advance();
                     continue;
                 }
@@ -252,8 +638,17 @@ impl<'input> Scanner<'input> {
                             Ok(_) => continue,
                             Err(e) => return Some(Err(e)),
                         },
+                        Some((_, '/')) => match self.next {
+                            Some((_, '/')) if self.emit_doc_comments => {
+                                return Some(Ok(self.scan_doc_comment(idx)))
+                            }
+                            _ => {
+                                self.skip_to_end_of_line();
+                                continue;
+                            }
+                        },
                         Some((_, c)) if c.is_id_char() => return self.scan_id(idx),
-                        _ => return Some(Ok((idx, Tok::SYMBOL("/".to_string()), idx + 1))),
+                        _ => return Some(Ok((idx, Tok::SYMBOL(String::new()), idx + 1))),
                     }
                 }
 
@@ -271,20 +666,26 @@ impl<'input> Scanner<'input> {
                     self.advance();
                     match self.current {
                         Some((_, c)) if c.is_alphabetic() => {
-                            self.advance();
-                            return Some(Ok((
+                            let end = loop {
+                                match self.current {
+                                    Some((_, c)) if c.is_alphabetic() => self.advance(),
+                                    Some((end, _)) => break end,
+                                    None => break self.input.len(),
+                                }
+                            };
+                            return Some(Ok((idx, Tok::STACKVAR(String::new()), end)));
+                        }
+                        Some((_, c)) => {
+                            return Some(Err(self.lexical_error(
                                 idx,
-                                Tok::STACKVAR("@".to_string() + &c.to_string()),
-                                idx + 2,
+                                format!("'@' must be followed by a letter, but saw {:?}", c),
                             )));
                         }
-                        _ => {
-                            let (line, column) = self.line_and_col(idx);
-                            return Some(Err(Error::LexicalError {
-                                line,
-                                column,
-                                message: "Invalid stack variable".to_string(),
-                            }));
+                        None => {
+                            return Some(Err(self.lexical_error(
+                                idx,
+                                "'@' must be followed by a letter, but saw EOF".to_string(),
+                            )));
                         }
                     }
                 }
@@ -297,64 +698,89 @@ impl<'input> Scanner<'input> {
                     match self.current {
                         Some((_, c)) if c.is_alphabetic() => {
                             self.advance();
+                            // After the first letter, a type variable can also
+                            // have digits and underscores - but not the other
+                            // characters of a symbol, so that "`T<< Foo" is still
+                            // a type variable followed by "<<".
                             loop {
                                 match self.current {
-                                    Some((_, c)) if c.is_alphabetic() => self.advance(),
+                                    Some((_, c)) if c.is_alphanumeric() || c == '_' => {
+                                        self.advance()
+                                    }
                                     Some((end, _)) => {
-                                        return Some(Ok((
-                                            idx,
-                                            Tok::TYPEVAR(self.input[idx..end].to_string()),
-                                            end - 1,
-                                        )))
+                                        return Some(Ok((idx, Tok::TYPEVAR(String::new()), end)))
                                     }
                                     None => {
                                         return Some(Ok((
                                             idx,
-                                            Tok::TYPEVAR(
-                                                self.input[idx..self.input.len()].to_string(),
-                                            ),
+                                            Tok::TYPEVAR(String::new()),
                                             self.input.len(),
                                         )))
                                     }
                                 }
                             }
                         }
-                        _ => {
-                            let (line, column) = self.line_and_col(idx);
-                            return Some(Err(Error::LexicalError {
-                                line,
-                                column,
-                                message: "Invalid type variable".to_string(),
-                            }));
+                        Some((_, c)) => {
+                            return Some(Err(self.lexical_error(
+                                idx,
+                                format!("'`' must be followed by a letter, but saw {:?}", c),
+                            )));
+                        }
+                        None => {
+                            return Some(Err(self.lexical_error(
+                                idx,
+                                "'`' must be followed by a letter, but saw EOF".to_string(),
+                            )));
                         }
                     }
                 }
                 Some((idx, '"')) => return self.scan_string(idx),
+                Some((idx, 'r'))
+                    if self.input[idx + 1..]
+                        .trim_start_matches('#')
+                        .starts_with('"') =>
+                {
+                    return self.scan_raw_string(idx)
+                }
                 Some((idx, c)) => {
                     if c == '-' {
                         // If it's a minus, and the next character is a digit,
                         // then send to number.
                         match self.next {
-                            Some((_, c)) if c.is_number() => return self.scan_number(idx),
+                            Some((_, c)) if c.is_ascii_digit() => return self.scan_number(idx),
                             _ => (),
                         }
                     }
+                    if c == '_' && matches!(self.next, Some((_, d)) if d.is_ascii_digit()) {
+                        // A separator can't start a number, either.
+                        return Some(Err(self.bad_digit_separator(idx, 10)));
+                    }
                     if c.is_id_start_char() {
                         return self.scan_id(idx);
-                    } else if c.is_number() || c == '-' {
+                    } else if c.is_ascii_digit() || c == '-' {
                         return self.scan_number(idx);
                     } else {
                         // error: skip past the error character, and then return the error.
                         self.advance();
-                        let (line, column) = self.line_and_col(idx);
-                        return Some(Err(Error::LexicalError {
-                            line,
-                            column,
-                            message: format!("Invalid token char: {}", c),
-                        }));
+                        if c.is_control() {
+                            return Some(Err(self.lexical_error(
+                                idx,
+                                format!("control character U+{:04X} is not allowed here", c as u32),
+                            )));
+                        }
+                        return Some(Err(
+                            self.lexical_error(idx, format!("Invalid token char: {}", c))
+                        ));
                     }
                 }
-                None => return None,
+                None => {
+                    if self.emit_eof && !self.eof_emitted {
+                        self.eof_emitted = true;
+                        let end = self.input.len();
+                        return Some(Ok((end, Tok::EOF, end)));
+                    }
+                    return None;
+                }
             }
         }
     }
@@ -364,29 +790,16 @@ impl<'input> Scanner<'input> {
         loop {
             match self.current {
                 Some((_, c)) if c.is_id_char() => self.advance(),
-                Some((idx, _)) => {
-                    return Some(Ok(self.id_or_reserved(
-                        start,
-                        idx,
-                        self.input[start..idx].to_string(),
-                    )))
-                }
-                None => {
-                    return Some(Ok(self.id_or_reserved(
-                        start,
-                        self.input.len(),
-                        self.input[start..self.input.len()].to_string(),
-                    )))
-                }
+                Some((idx, _)) => return Some(Ok(self.id_or_reserved(start, idx))),
+                None => return Some(Ok(self.id_or_reserved(start, self.input.len()))),
             }
         }
     }
 
-    fn id_or_reserved(&self, start: usize, end: usize, name: String) -> (usize, Tok, usize) {
-        if self.reserved.contains_key(&name) {
-            return (start, self.reserved.get(&name).unwrap().clone(), end);
-        } else {
-            return (start, Tok::SYMBOL(name), end);
+    fn id_or_reserved(&self, start: usize, end: usize) -> (usize, Tok, usize) {
+        match self.reserved.get(&self.input[start..end]) {
+            Some(tok) => return (start, tok.clone(), end),
+            None => return (start, Tok::SYMBOL(String::new()), end),
         }
     }
 
@@ -408,51 +821,175 @@ impl<'input> Scanner<'input> {
                     self.advance();
                 }
                 None => {
-                    let (line, column) = self.line_and_col(start);
-                    return Err(Error::LexicalError {
-                        line,
-                        column,
-                        message: "Unterminated comment".to_string(),
-                    });
+                    return Err(self.lexical_error(start, "Unterminated comment".to_string()));
                 }
             }
         }
     }
 
+    /// Skip the rest of a "//" comment, including the line terminator.
+    /// A line can end with "\n", "\r\n", or a lone "\r".
+    fn skip_to_end_of_line(&mut self) {
+        loop {
+            match self.current {
+                Some((_, '\n')) => {
+                    self.advance();
+                    return;
+                }
+                Some((_, '\r')) => {
+                    self.advance();
+                    if let Some((_, '\n')) = self.current {
+                        self.advance();
+                    }
+                    return;
+                }
+                Some(_) => self.advance(),
+                None => return,
+            }
+        }
+    }
+
+    /// Scan a "///" documentation comment. The token holds the text
+    /// following the "///", up to (but not including) the end of the line.
+    fn scan_doc_comment(&mut self, start: usize) -> (usize, Tok, usize) {
+        self.advance();
+        self.advance();
+        let end = loop {
+            match self.current {
+                Some((i, '\n')) | Some((i, '\r')) => break i,
+                Some(_) => self.advance(),
+                None => break self.input.len(),
+            }
+        };
+        self.skip_to_end_of_line();
+        return (
+            start,
+            Tok::DOCCOMMENT(self.input[start + 3..end].to_string()),
+            end,
+        );
+    }
+
     /// Scan a numeric literal.
     fn scan_number(&mut self, start: usize) -> Option<ScannerResult<'input>> {
-        let mut count = 0;
         if let Some((_, c)) = self.current {
             if c == '-' {
                 self.advance();
             }
         }
+        if let Some((_, '0')) = self.current {
+            match self.next {
+                Some((_, 'b')) => {
+                    self.advance();
+                    self.advance();
+                    return self.scan_radix_number(start, 2);
+                }
+                Some((_, 'o')) => {
+                    self.advance();
+                    self.advance();
+                    return self.scan_radix_number(start, 8);
+                }
+                _ => (),
+            }
+        }
         loop {
             if let Some((i, c)) = self.current {
-                count = count + 1;
                 if c.is_ascii_digit() {
                     self.advance();
                     continue;
-                } else if c == '.' {
+                } else if c == '_' {
+                    match self.scan_digit_separator(i, 10) {
+                        Ok(_) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
+                } else if c == '.' && matches!(self.next, Some((_, d)) if d.is_ascii_digit()) {
+                    // Only a "." followed by a digit makes a float; otherwise
+                    // the "." is left to start the next token, as in "3.foo".
                     self.advance();
                     return self.scan_float(start);
+                } else if (c == 'e' || c == 'E') && self.starts_exponent(i + 1) {
+                    self.advance();
+                    return self.scan_float_exponent(start);
                 } else {
-                    return Some(Ok((
-                        start,
-                        Tok::INTLIT(self.input[start..i].parse::<i64>().unwrap()),
-                        i,
-                    )));
+                    return Some(self.int_token(start, i));
                 }
             } else {
-                return Some(Ok((
-                    start,
-                    Tok::INTLIT(self.input[start..(start + count)].parse::<i64>().unwrap()),
-                    start + count,
-                )));
+                return Some(self.int_token(start, self.input.len()));
+            }
+        }
+    }
+
+    /// Produce the integer token for the decimal literal spanning
+    /// start..end, or an error if it doesn't fit in an i64.
+    fn int_token(&self, start: usize, end: usize) -> ScannerResult<'input> {
+        match self.input[start..end].replace('_', "").parse::<i64>() {
+            Ok(v) => Ok((start, Tok::INTLIT(v), end)),
+            Err(_) => Err(self.lexical_error(start, "integer literal out of range".to_string())),
+        }
+    }
+
+    /// Scan the digits of a binary or octal integer literal.
+    /// This state is entered from scan_number after it has consumed
+    /// the "0b" or "0o" prefix, and returns a token containing the
+    /// whole literal, including any leading sign.
+    fn scan_radix_number(&mut self, start: usize, radix: u32) -> Option<ScannerResult<'input>> {
+        let mut digits = String::new();
+        if self.input[start..].starts_with('-') {
+            digits.push('-');
+        }
+        let digits_start = digits.len();
+        let end = loop {
+            match self.current {
+                Some((_, c)) if c.is_digit(radix) => {
+                    digits.push(c);
+                    self.advance();
+                }
+                Some((pos, '_')) => {
+                    if let Err(e) = self.scan_digit_separator(pos, radix) {
+                        return Some(Err(e));
+                    }
+                }
+                Some((pos, c)) if c.is_ascii_alphanumeric() => {
+                    // skip the rest of the malformed literal, so that it
+                    // doesn't turn into a spray of bogus tokens.
+                    while let Some((_, c)) = self.current {
+                        if !c.is_ascii_alphanumeric() {
+                            break;
+                        }
+                        self.advance();
+                    }
+                    return Some(Err(self.lexical_error(
+                        pos,
+                        format!("Invalid digit '{}' in base {} literal", c, radix),
+                    )));
+                }
+                Some((end, _)) => break end,
+                None => break self.input.len(),
             }
+        };
+        if digits.len() == digits_start {
+            return Some(Err(self.lexical_error(
+                end,
+                format!("Expected base {} digits after the radix prefix", radix),
+            )));
+        }
+        match i64::from_str_radix(&digits, radix) {
+            Ok(v) => Some(Ok((start, Tok::INTLIT(v), end))),
+            Err(_) => Some(Err(
+                self.lexical_error(start, "integer literal out of range".to_string())
+            )),
         }
     }
 
+    /// Check whether the input at pos looks like the digits of an
+    /// exponent. This lets an integer followed by "e" be scanned as
+    /// a float ("6e23") without swallowing something like "2each".
+    fn starts_exponent(&self, pos: usize) -> bool {
+        return self.input[pos..]
+            .strip_prefix(|c| c == '-' || c == '+')
+            .unwrap_or(&self.input[pos..])
+            .starts_with(|c: char| c.is_ascii_digit());
+    }
+
     /// Scan the fractional part of a floating point literal.
     /// This state is only entered from scan_number, and returns a token
     /// containing everything matched by both scan_number and this state.
@@ -462,16 +999,30 @@ impl<'input> Scanner<'input> {
                 if c.is_ascii_digit() {
                     self.advance();
                     continue;
-                } else if c == 'e' {
+                } else if c == '_' {
+                    match self.scan_digit_separator(i, 10) {
+                        Ok(_) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
+                } else if c == 'e' || c == 'E' {
                     self.advance();
                     return self.scan_float_exponent(start);
+                } else if c == '.' && matches!(self.next, Some((_, d)) if d.is_ascii_digit()) {
+                    // A second decimal point, like "1.2.3".
+                    while let Some((_, c)) = self.current {
+                        if !(c.is_ascii_digit() || c == '.') {
+                            break;
+                        }
+                        self.advance();
+                    }
+                    return Some(Err(
+                        self.lexical_error(start, "Malformed float literal".to_string())
+                    ));
                 } else {
-                    return Some(Ok((
-                        start,
-                        Tok::FLOATLIT(self.input[start..i].parse::<f64>().unwrap()),
-                        i,
-                    )));
+                    return Some(self.float_token(start, i));
                 }
+            } else {
+                return Some(self.float_token(start, self.input.len()));
             }
         }
     }
@@ -481,7 +1032,7 @@ impl<'input> Scanner<'input> {
     /// containing everything matched by scan_number, scan_float, and this state.
     fn scan_float_exponent(&mut self, start: usize) -> Option<ScannerResult<'input>> {
         if let Some((_, c)) = self.current {
-            if c == '-' {
+            if c == '-' || c == '+' {
                 self.advance();
             }
         }
@@ -490,88 +1041,253 @@ impl<'input> Scanner<'input> {
                 if c.is_ascii_digit() {
                     self.advance();
                     continue;
+                } else if c == '_' {
+                    match self.scan_digit_separator(i, 10) {
+                        Ok(_) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
                 } else {
-                    return Some(Ok((
-                        start,
-                        Tok::FLOATLIT(self.input[start..i].parse::<f64>().unwrap()),
-                        i,
-                    )));
+                    return Some(self.float_token(start, i));
                 }
             } else {
-                return Some(Ok((
-                    start,
-                    Tok::FLOATLIT(self.input[start..].parse::<f64>().unwrap()),
-                    self.input.len(),
-                )));
+                return Some(self.float_token(start, self.input.len()));
             }
         }
     }
 
+    /// Produce the float token for the literal spanning start..end,
+    /// with its digit separators removed, or an error if the text isn't
+    /// a well-formed float, like "1.0e" with no exponent digits.
+    fn float_token(&self, start: usize, end: usize) -> ScannerResult<'input> {
+        let text = self.input[start..end].replace('_', "");
+        match float_value(&text) {
+            Some(_) => return Ok((start, Tok::FLOATLIT(text), end)),
+            None => {
+                return Err(self.lexical_error(start, "Malformed float literal".to_string()));
+            }
+        }
+    }
+
+    /// Consume a "_" digit separator inside of a numeric literal.
+    ///
+    /// Separators are only allowed between two digits of the literal's
+    /// radix, so "1_000" and "0b1111_0000" are fine, but "_100", "100_" and
+    /// "1__0" are errors. On an error, the rest of the malformed literal
+    /// is skipped.
+    fn scan_digit_separator(&mut self, pos: usize, radix: u32) -> Result<(), Error> {
+        let after_digit = self.input[..pos].ends_with(|c: char| c.is_digit(radix));
+        let before_digit = match self.next {
+            Some((_, c)) => c.is_digit(radix),
+            None => false,
+        };
+        if after_digit && before_digit {
+            self.advance();
+            return Ok(());
+        }
+        return Err(self.bad_digit_separator(pos, radix));
+    }
+
+    /// Report a misplaced digit separator at pos, skipping the rest of
+    /// the malformed literal.
+    fn bad_digit_separator(&mut self, pos: usize, radix: u32) -> Error {
+        while let Some((_, c)) = self.current {
+            if !(c.is_digit(radix) || c == '_') {
+                break;
+            }
+            self.advance();
+        }
+        return self.lexical_error(
+            pos,
+            "Digit separator '_' must appear between two digits".to_string(),
+        );
+    }
+
     /// Scan a string literal.
+    ///
+    /// The token holds the string's value, with escape sequences replaced
+    /// by the characters they stand for. A backslash at the end of a line
+    /// is a line continuation: the backslash and the line break are
+    /// dropped from the value.
     fn scan_string(&mut self, start: usize) -> Option<ScannerResult<'input>> {
+        if self.input[start..].starts_with("\"\"\"") {
+            return self.scan_multiline_string(start);
+        }
         self.advance();
+        let mut value = String::new();
+        // A bad escape is reported once the rest of the string has been
+        // scanned, so that its closing quote doesn't start another one.
+        let mut bad_escape = None;
         loop {
             if let Some((i, c)) = self.current {
                 match c {
                     '"' => {
                         self.advance();
-                        return Some(Ok((
-                            start,
-                            Tok::STRINGLIT(self.input[start + 1..i].to_string()),
-                            i + 1,
-                        )));
+                        if let Some(e) = bad_escape {
+                            return Some(Err(e));
+                        }
+                        return Some(Ok((start, Tok::STRINGLIT(value), i + 1)));
                     }
                     '\\' => {
                         self.advance();
                         match self.scan_string_escape() {
-                            Err(e) => return Some(Err(e)),
-                            Ok(_) => continue,
+                            Err(e) => {
+                                bad_escape.get_or_insert(e);
+                            }
+                            Ok(Some(c)) => value.push(c),
+                            Ok(None) => (),
                         }
                     }
-                    _ => self.advance(),
+                    _ => {
+                        value.push(c);
+                        self.advance()
+                    }
+                }
+            } else {
+                return Some(Err(bad_escape.unwrap_or_else(|| {
+                    self.lexical_error(start, "Unterminated string literal".to_string())
+                })));
+            }
+        }
+    }
+
+    /// Scan a triple-quoted string literal, which can span multiple
+    /// lines. Everything between the opening and closing triple quotes,
+    /// including newlines and lone quotes, is part of the string.
+    fn scan_multiline_string(&mut self, start: usize) -> Option<ScannerResult<'input>> {
+        for _ in 0..3 {
+            self.advance();
+        }
+        let body_start = start + 3;
+        loop {
+            match self.current {
+                Some((i, '"')) if self.input[i..].starts_with("\"\"\"") => {
+                    for _ in 0..3 {
+                        self.advance();
+                    }
+                    return Some(Ok((
+                        start,
+                        Tok::STRINGLIT(self.input[body_start..i].to_string()),
+                        i + 3,
+                    )));
+                }
+                Some(_) => self.advance(),
+                None => {
+                    return Some(Err(self.lexical_error(
+                        start,
+                        "Unterminated multi-line string literal".to_string(),
+                    )));
                 }
             }
         }
     }
 
-    fn scan_string_escape(&mut self) -> Result<char, Error> {
+    /// Scan a raw string literal, like r"C:\new" or r#"say "hi""#.
+    ///
+    /// Nothing inside a raw string is treated as an escape. The literal
+    /// ends at the first quote that's followed by the same number of "#"s
+    /// that preceded the opening quote.
+    fn scan_raw_string(&mut self, start: usize) -> Option<ScannerResult<'input>> {
+        // Skip the "r", and count the "#"s up to the opening quote.
+        self.advance();
+        let mut hashes = String::new();
+        while let Some((_, '#')) = self.current {
+            hashes.push('#');
+            self.advance();
+        }
+        self.advance();
+        let body_start = start + 2 + hashes.len();
+        loop {
+            match self.current {
+                Some((i, '"')) if self.input[i + 1..].starts_with(&hashes) => {
+                    for _ in 0..=hashes.len() {
+                        self.advance();
+                    }
+                    return Some(Ok((
+                        start,
+                        Tok::STRINGLIT(self.input[body_start..i].to_string()),
+                        i + 1 + hashes.len(),
+                    )));
+                }
+                Some(_) => self.advance(),
+                None => {
+                    return Some(Err(self.lexical_error(
+                        start,
+                        "Unterminated raw string literal".to_string(),
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Scan an escape sequence, starting from the character after the
+    /// backslash, and consume the whole sequence.
+    ///
+    /// Returns the character that the escape stands for, or None for
+    /// a line continuation, which stands for nothing.
+    fn scan_string_escape(&mut self) -> Result<Option<char>, Error> {
         if let Some((pos, c)) = self.current {
+            let simple = match c {
+                '\\' => Some('\\'),
+                'n' => Some('\n'),
+                'r' => Some('\r'),
+                '0' => Some('\0'),
+                't' => Some('\t'),
+                '"' => Some('"'),
+                '\'' => Some('\''),
+                _ => None,
+            };
+            if simple.is_some() {
+                self.advance();
+                return Ok(simple);
+            }
             match c {
-                '\\' => return Ok('\\'),
-                'n' => return Ok('\n'),
-                'r' => return Ok('\r'),
-                '0' => return Ok('\0'),
-                't' => return Ok('\t'),
-                '"' => return Ok('"'),
+                '\n' => {
+                    self.advance();
+                    return Ok(None);
+                }
+                '\r' => {
+                    self.advance();
+                    if let Some((_, '\n')) = self.current {
+                        self.advance();
+                    }
+                    return Ok(None);
+                }
                 'x' => {
                     self.advance();
                     // scan two hex digits
                     let digits = self.swallow(2, 2, |q: char| q.is_ascii_hexdigit())?;
-                    return Ok(char::from_u32(u32::from_str_radix(&digits, 16).unwrap()).unwrap());
+                    return Ok(Some(self.code_point(pos, &digits)?));
                 }
                 'u' => {
                     self.advance();
                     self.swallow_char('{')?;
                     let digits = self.swallow(1, 6, |c| c.is_ascii_hexdigit())?;
                     self.swallow_char('}')?;
-                    return Ok(char::from_u32(u32::from_str_radix(&digits, 16).unwrap()).unwrap());
+                    return Ok(Some(self.code_point(pos, &digits)?));
                 }
                 _ => {
-                    let (line, column) = self.line_and_col(pos);
-                    return Err(Error::LexicalError {
-                        line,
-                        column,
-                        message: "Invalid escape sequence".to_string(),
-                    });
+                    return Err(self.lexical_error(pos, "Invalid escape sequence".to_string()));
                 }
             }
         } else {
-            let (line, column) = self.line_and_col(self.input.len());
-            return Err(Error::LexicalError {
-                line,
-                column,
-                message: "Unterminated escape sequence".to_string(),
-            });
+            return Err(
+                self.lexical_error(self.input.len(), "Unterminated escape sequence".to_string())
+            );
+        }
+    }
+
+    /// Convert the hex digits of a numeric escape sequence into a char,
+    /// or return an error if they aren't a valid unicode scalar value -
+    /// for example, a surrogate like D800, or anything above 10FFFF.
+    fn code_point(&self, pos: usize, digits: &str) -> Result<char, Error> {
+        match u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(c) => return Ok(c),
+            None => {
+                return Err(self.lexical_error(pos, "invalid unicode code point".to_string()));
+            }
         }
     }
 
@@ -599,25 +1315,20 @@ impl<'input> Scanner<'input> {
                     if i >= min {
                         return Ok(result);
                     } else {
-                        let (line, column) = self.line_and_col(pos);
-                        return Err(Error::LexicalError {
-                            line,
-                            column,
-                            message: format!("Invalid token: Expected at least {} chars", min)
-                                .to_string(),
-                        });
+                        return Err(self.lexical_error(
+                            pos,
+                            format!("Invalid token: Expected at least {} chars", min).to_string(),
+                        ));
                     }
                 }
             } else {
                 if i >= min {
                     return Ok(result);
                 } else {
-                    let (line, column) = self.line_and_col(self.input.len());
-                    return Err(Error::LexicalError {
-                        line,
-                        column,
-                        message: format!("Expected at least {} characters", min).to_string(),
-                    });
+                    return Err(self.lexical_error(
+                        self.input.len(),
+                        format!("Expected at least {} characters", min).to_string(),
+                    ));
                 }
             }
         }
@@ -632,34 +1343,35 @@ impl<'input> Scanner<'input> {
                 self.advance();
                 return Ok(());
             } else {
-                let (line, column) = self.line_and_col(pos);
-                return Err(Error::LexicalError {
-                    line,
-                    column,
-                    message: format!("Expected '{}', but saw '{}'", c, q).to_string(),
-                });
+                return Err(self.lexical_error(
+                    pos,
+                    format!("Expected '{}', but saw '{}'", c, q).to_string(),
+                ));
             }
         } else {
-            let (line, column) = self.line_and_col(self.input.len());
-            return Err(Error::LexicalError {
-                line,
-                column,
-                message: format!("Expected character, but saw EOF").to_string(),
-            });
+            return Err(self.lexical_error(
+                self.input.len(),
+                format!("Expected character, but saw EOF").to_string(),
+            ));
         }
     }
 
     fn scan_char_escape(&mut self, start: usize) -> ScannerResult {
-        let c = self.scan_string_escape()?;
+        // skip the backslash.
+        self.advance();
+        let c = match self.scan_string_escape()? {
+            Some(c) => c,
+            None => {
+                return Err(self.lexical_error(start, "Invalid character literal".to_string()));
+            }
+        };
         match self.current {
-            Some((end, '\'')) => return Ok((start, Tok::CHARLIT(c), end)),
+            Some((end, '\'')) => {
+                self.advance();
+                return Ok((start, Tok::CHARLIT(c), end + 1));
+            }
             _ => {
-                let (line, column) = self.line_and_col(start);
-                return Err(Error::LexicalError {
-                    line,
-                    column,
-                    message: "Unterminated char literal".to_string(),
-                });
+                return Err(self.lexical_error(start, "Unterminated char literal".to_string()));
             }
         }
     }
@@ -676,34 +1388,24 @@ impl<'input> Scanner<'input> {
                     match self.current {
                         Some((end, '\'')) => {
                             self.advance();
-                            return Ok((start, Tok::CHARLIT(c), end));
+                            return Ok((start, Tok::CHARLIT(c), end + 1));
                         }
                         Some((i, _)) => {
-                            let (line, column) = self.line_and_col(i);
-                            return Err(Error::LexicalError {
-                                line,
-                                column,
-                                message: "Invalid character literal".to_string(),
-                            });
+                            return Err(
+                                self.lexical_error(i, "Invalid character literal".to_string())
+                            );
                         }
                         _ => {
-                            let (line, column) = self.line_and_col(self.input.len());
-                            return Err(Error::LexicalError {
-                                line,
-                                column,
-                                message: "Invalid character literal".to_string(),
-                            });
+                            return Err(self.lexical_error(
+                                self.input.len(),
+                                "Invalid character literal".to_string(),
+                            ));
                         }
                     }
                 }
             }
         } else {
-            let (line, column) = self.line_and_col(start);
-            return Err(Error::LexicalError {
-                line,
-                column,
-                message: "Invalid character literal".to_string(),
-            });
+            return Err(self.lexical_error(start, "Invalid character literal".to_string()));
         }
     }
 }
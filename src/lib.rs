@@ -0,0 +1,130 @@
+//! The Schism language front end: a scanner, a parser, and the AST that
+//! it produces, along with a handful of passes over that AST.
+//!
+//! The simplest way in is to parse a whole source file into a sect:
+//!
+//! ```
+//! use schism::{lex::Scanner, schism_parser::SectParser};
+//!
+//! let source = "fun double ( int -- int ) is dup + end";
+//! let sect = SectParser::new()
+//!     .parse(Scanner::new("example.schism".to_string(), source))
+//!     .unwrap();
+//! assert_eq!(1, sect.decls.len());
+//! ```
+//!
+//! or, for short, [parse_sect].
+
+#[macro_use]
+extern crate lalrpop_util;
+
+lalrpop_mod!(pub schism_parser); // synthesized by LALRPOP
+
+pub mod ast;
+pub mod diagnostic;
+pub mod effects;
+pub mod error;
+pub mod fold;
+pub mod format;
+pub mod lex;
+pub mod lint;
+pub mod metrics;
+pub mod resolve;
+pub mod symbols;
+pub mod twist;
+pub mod visit;
+
+#[cfg(test)]
+mod tests;
+
+/// The source id used in errors from the parse helpers.
+const STRING_SOURCE: &str = "<string>";
+
+/// Convert an error from one of the parsers into an [error::Error].
+///
+/// If the brackets in the text don't match up before the point where
+/// the parse failed, that's reported instead, since it's a better
+/// explanation of what went wrong.
+fn convert_error(
+    text: &str,
+    err: lalrpop_util::ParseError<usize, lex::Tok, error::Error>,
+) -> error::Error {
+    let err = error::Error::from_parse_error(STRING_SOURCE, text, err);
+    if let Err(delim) = lex::Scanner::new(STRING_SOURCE.to_string(), text).check_delimiters() {
+        if delim.offset() <= err.offset() {
+            return delim;
+        }
+    }
+    return err;
+}
+
+/// Parse a complete sect from a string, converting any failure into
+/// an [error::Error].
+pub fn parse_sect(text: &str) -> Result<ast::Sect, error::Error> {
+    ast::StackImage::reset_index();
+    return schism_parser::SectParser::new()
+        .parse(lex::Scanner::new(STRING_SOURCE.to_string(), text))
+        .map_err(|e| convert_error(text, e));
+}
+
+/// Parse a single declaration from a string.
+pub fn parse_decl(text: &str) -> Result<ast::Decl, error::Error> {
+    ast::StackImage::reset_index();
+    return schism_parser::DeclParser::new()
+        .parse(lex::Scanner::new(STRING_SOURCE.to_string(), text))
+        .map_err(|e| convert_error(text, e));
+}
+
+/// Parse a single expression from a string.
+pub fn parse_expr(text: &str) -> Result<ast::Expr, error::Error> {
+    ast::StackImage::reset_index();
+    return schism_parser::ExprParser::new()
+        .parse(lex::Scanner::new(STRING_SOURCE.to_string(), text))
+        .map_err(|e| convert_error(text, e));
+}
+
+/// Parse a sect from a string, recovering from errors so that one
+/// mistake doesn't hide the rest.
+///
+/// When a declaration fails to parse, the parser skips ahead to the
+/// start of the next one, and the broken declaration is left out of
+/// the sect. Lexical errors are reported too, and the characters that
+/// caused them are skipped. The errors are returned in the order that
+/// they appear in the text.
+pub fn parse_recovering(text: &str) -> (ast::Sect, Vec<error::Error>) {
+    ast::StackImage::reset_index();
+    let mut errors = Vec::new();
+    let mut tokens = Vec::new();
+    for result in lex::Scanner::new_lenient(STRING_SOURCE.to_string(), text) {
+        match result {
+            Ok(tok) => tokens.push(tok),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    let result = schism_parser::RecoveringSectParser::new().parse(tokens.into_iter().map(Ok));
+    let (sect, recovered) = match result {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            errors.push(convert_error(text, e));
+            let empty = ast::Sect {
+                uses: Vec::new(),
+                decls: Vec::new(),
+            };
+            (empty, Vec::new())
+        }
+    };
+    for r in recovered {
+        // convert_error reports the first bad delimiter in the text in
+        // place of any parse error after it, but it only needs to be
+        // reported once.
+        let converted = convert_error(text, r.error.clone());
+        if errors.iter().any(|e| e.offset() == converted.offset()) {
+            errors.push(error::Error::from_parse_error(STRING_SOURCE, text, r.error));
+        } else {
+            errors.push(converted);
+        }
+    }
+    errors.sort_by_key(|e| e.offset());
+    return (sect, errors);
+}
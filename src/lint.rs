@@ -0,0 +1,107 @@
+use crate::ast::{Decl, Expr, Identifier, SType, Sect, Span, StackEffect};
+use crate::diagnostic::Diagnostic;
+use crate::visit::{self, Visitor};
+use std::collections::HashSet;
+
+/// Collects every unqualified name that's used in a call or a type.
+struct NameCollector {
+    names: HashSet<String>,
+}
+
+impl NameCollector {
+    fn add(&mut self, id: &Identifier) {
+        if let Identifier::Simple(s) = id {
+            self.names.insert(s.0.clone());
+        }
+    }
+}
+
+impl Visitor for NameCollector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::FunCall(f) = expr {
+            self.add(&f.id);
+        }
+        visit::walk_expr(self, expr)
+    }
+
+    fn visit_type(&mut self, s_type: &SType) {
+        match s_type {
+            SType::Simple(id) | SType::Parametric(_, id) => self.add(id),
+            _ => (),
+        }
+        visit::walk_type(self, s_type)
+    }
+
+    fn visit_stack_effect(&mut self, effect: &StackEffect) {
+        for d in &effect.effect_domains {
+            self.add(d);
+        }
+        visit::walk_stack_effect(self, effect)
+    }
+}
+
+/// Warn about names that a sect imports with "use" but never refers
+/// to. Only imports that list their names are checked: there's no
+/// way to tell whether a bare "use" of a whole sect is needed.
+pub fn check_unused_imports(sect: &Sect) -> Vec<Diagnostic> {
+    let mut collector = NameCollector {
+        names: HashSet::new(),
+    };
+    collector.visit_sect(sect);
+
+    let mut result = Vec::new();
+    for u in &sect.uses {
+        for name in u.names.iter().flatten() {
+            if !collector.names.contains(&name.0) {
+                result.push(Diagnostic::warning(
+                    u.span,
+                    format!("'{}' is imported but never used", name.0),
+                ));
+            }
+        }
+    }
+    return result;
+}
+
+/// Collects the method name and span of every method send.
+struct SendCollector {
+    sends: Vec<(String, Span)>,
+}
+
+impl Visitor for SendCollector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::MethodCall(m) = expr {
+            self.sends.push((m.sym.0.clone(), m.span));
+        }
+        visit::walk_expr(self, expr)
+    }
+}
+
+/// Warn about method sends ("<- name") where no struct in the sect
+/// defines a method with that name. The receiver's type isn't known
+/// until runtime, so any struct's method will do; and since a method
+/// could come from a struct in an imported sect, this is only a
+/// warning.
+pub fn check_method_sends(sect: &Sect) -> Vec<Diagnostic> {
+    let mut methods = HashSet::new();
+    for d in &sect.decls {
+        if let Decl::Struct(s) = d {
+            for m in &s.methods {
+                methods.insert(m.name.0.clone());
+            }
+        }
+    }
+    let mut collector = SendCollector { sends: Vec::new() };
+    collector.visit_sect(sect);
+
+    let mut result = Vec::new();
+    for (name, span) in collector.sends {
+        if !methods.contains(&name) {
+            result.push(Diagnostic::warning(
+                span,
+                format!("no struct defines a method '{}'", name),
+            ));
+        }
+    }
+    return result;
+}
@@ -0,0 +1,67 @@
+use crate::ast::{Expr, FunctionDecl, MethodDecl};
+use crate::format::ToSource;
+use crate::visit::{self, Visitor};
+use std::collections::HashSet;
+
+/// Size and complexity measures of a function or method body, for
+/// linters.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DefinitionMetrics {
+    /// The number of expressions in the body, counting the ones
+    /// inside of conds, loops, blocks and list and map literals.
+    pub statement_count: usize,
+    /// How deeply conds and loops are nested; a body with no conds or
+    /// loops has a nesting of 0.
+    pub max_nesting: usize,
+    /// The names of the functions that the body calls.
+    pub invoked_names: HashSet<String>,
+}
+
+struct MetricsCollector {
+    metrics: DefinitionMetrics,
+    depth: usize,
+}
+
+impl Visitor for MetricsCollector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.metrics.statement_count += 1;
+        match expr {
+            Expr::FunCall(f) => {
+                self.metrics.invoked_names.insert(f.id.to_source());
+            }
+            Expr::Cond(_) | Expr::Loop(_) => {
+                self.depth += 1;
+                self.metrics.max_nesting = self.metrics.max_nesting.max(self.depth);
+                visit::walk_expr(self, expr);
+                self.depth -= 1;
+                return;
+            }
+            _ => (),
+        }
+        visit::walk_expr(self, expr)
+    }
+}
+
+/// Compute the metrics of a sequence of expressions.
+pub fn body_metrics(body: &[Expr]) -> DefinitionMetrics {
+    let mut collector = MetricsCollector {
+        metrics: DefinitionMetrics::default(),
+        depth: 0,
+    };
+    for e in body {
+        collector.visit_expr(e);
+    }
+    return collector.metrics;
+}
+
+impl FunctionDecl {
+    pub fn metrics(&self) -> DefinitionMetrics {
+        return body_metrics(&self.body);
+    }
+}
+
+impl MethodDecl {
+    pub fn metrics(&self) -> DefinitionMetrics {
+        return body_metrics(&self.body);
+    }
+}
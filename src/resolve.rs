@@ -0,0 +1,55 @@
+use crate::ast::{Expr, Identifier, LocalExpr};
+
+/// Find the references to locals in a body.
+///
+/// "local x" pops the top of the stack into a new local named x, and
+/// from then on, x pushes its value. The parser can't tell that use
+/// of x apart from a call to a function named x, so it produces a
+/// function call; this pass rewrites the calls that refer to a local
+/// that's in scope into [Expr::LocalRef]s.
+///
+/// A local is in scope from its declaration to the end of the body
+/// that declared it, including any bodies nested inside of that, like
+/// the branches of a cond or a block. A local declared inside a nested
+/// body goes out of scope at the end of that body.
+pub fn resolve_locals(body: &mut [Expr]) {
+    resolve_in(body, &mut Vec::new());
+}
+
+fn resolve_in(body: &mut [Expr], scope: &mut Vec<String>) {
+    let outer = scope.len();
+    for e in body.iter_mut() {
+        match e {
+            Expr::Local(l) => scope.push(l.name.0.clone()),
+            Expr::FunCall(call) => {
+                if let Identifier::Simple(name) = &call.id {
+                    if call.type_args.is_none() && scope.contains(&name.0) {
+                        *e = Expr::LocalRef(LocalExpr {
+                            name: name.clone(),
+                            span: call.span,
+                        });
+                    }
+                }
+            }
+            Expr::List(l) => {
+                for v in l.values.iter_mut() {
+                    resolve_in(v, scope);
+                }
+            }
+            Expr::Map(m) => {
+                for (k, v) in m.values.iter_mut() {
+                    resolve_in(k, scope);
+                    resolve_in(v, scope);
+                }
+            }
+            Expr::Cond(c) => {
+                resolve_in(&mut c.true_block, scope);
+                resolve_in(&mut c.false_block, scope);
+            }
+            Expr::Loop(l) => resolve_in(&mut l.body, scope),
+            Expr::Block(b) => resolve_in(&mut b.body, scope),
+            _ => (),
+        }
+    }
+    scope.truncate(outer);
+}
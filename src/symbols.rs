@@ -0,0 +1,42 @@
+use crate::ast::{Decl, Sect};
+use std::collections::HashMap;
+
+/// The names declared at the top level of a sect.
+///
+/// Types (structs and aliases) and values (functions, vars and
+/// consts) live in separate namespaces, so a struct "Foo" and a
+/// function "Foo" can both be declared in the same sect. If a name
+/// is declared more than once in the same namespace, the first
+/// declaration is the one that's recorded.
+pub struct SymbolTable<'a> {
+    types: HashMap<String, &'a Decl>,
+    values: HashMap<String, &'a Decl>,
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn new(sect: &'a Sect) -> SymbolTable<'a> {
+        let mut table = SymbolTable {
+            types: HashMap::new(),
+            values: HashMap::new(),
+        };
+        for d in &sect.decls {
+            let (namespace, name) = match d {
+                Decl::Struct(s) => (&mut table.types, &s.name),
+                Decl::Alias(a) => (&mut table.types, &a.name),
+                Decl::Function(f) => (&mut table.values, &f.name),
+                Decl::Var(v) => (&mut table.values, &v.name),
+                Decl::Const(c) => (&mut table.values, &c.name),
+            };
+            namespace.entry(name.0.clone()).or_insert(d);
+        }
+        return table;
+    }
+
+    pub fn lookup_type(&self, name: &str) -> Option<&'a Decl> {
+        return self.types.get(name).copied();
+    }
+
+    pub fn lookup_value(&self, name: &str) -> Option<&'a Decl> {
+        return self.values.get(name).copied();
+    }
+}
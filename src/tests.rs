@@ -1,4 +1,7 @@
 use crate::ast::Renderable;
+use crate::error::Error;
+use crate::twist::{Twist, Twistable};
+use crate::visit::{self, Visitor};
 use crate::{ast, lex, schism_parser};
 
 fn assert_token_is<'input>(result: Option<lex::ScannerResult<'input>>, expected: lex::Tok) {
@@ -7,6 +10,26 @@ fn assert_token_is<'input>(result: Option<lex::ScannerResult<'input>>, expected:
     assert_eq!(expected, t)
 }
 
+fn assert_lex_error_message<'input>(result: Option<lex::ScannerResult<'input>>, expected: &str) {
+    match result {
+        Some(Err(Error::LexicalError { message, .. })) => assert_eq!(expected, message),
+        other => panic!("Expected a lexical error, but got {:?}", other),
+    }
+}
+
+fn assert_lex_error_at<'input>(
+    result: Option<lex::ScannerResult<'input>>,
+    line: usize,
+    column: usize,
+) {
+    match result {
+        Some(Err(Error::LexicalError {
+            line: l, column: c, ..
+        })) => assert_eq!((line, column), (l, c)),
+        other => panic!("Expected a lexical error, but got {:?}", other),
+    }
+}
+
 #[test]
 pub fn test_scan_symbols_and_idents() {
     let mut lex = lex::Scanner::new("foo".to_string(), "foo bar/baz + 23\nbli");
@@ -18,6 +41,44 @@ pub fn test_scan_symbols_and_idents() {
     assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("bli".to_string()))
 }
 
+#[test]
+pub fn test_scan_short_identifiers() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "T");
+    assert_eq!(
+        (0, lex::Tok::SYMBOL("T".to_string()), 1),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert!(lex.scan_token().is_none());
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "Int)");
+    assert_eq!(
+        (0, lex::Tok::SYMBOL("Int".to_string()), 3),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert_eq!((3, lex::Tok::RPAREN, 4), lex.scan_token().unwrap().unwrap());
+}
+
+#[test]
+pub fn test_scan_stack_vars() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "@bogus @A) @");
+    assert_eq!(
+        (0, lex::Tok::STACKVAR("@bogus".to_string()), 6),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::STACKVAR("@A".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::RPAREN);
+    assert_lex_error_message(
+        lex.scan_token(),
+        "'@' must be followed by a letter, but saw EOF",
+    );
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "@ ");
+    assert_lex_error_message(
+        lex.scan_token(),
+        "'@' must be followed by a letter, but saw ' '",
+    );
+}
+
 #[test]
 pub fn test_scan_syntax() {
     let mut lex = lex::Scanner::new(
@@ -59,11 +120,491 @@ pub fn test_scan_literals() {
         lex::Tok::STRINGLIT("this is a string".to_string()),
     );
     assert_token_is(lex.scan_token(), lex::Tok::INTLIT(27));
-    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT(13.2));
-    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT(-4.0e5));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("13.2".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("-4.0e5".to_string()));
     assert_token_is(lex.scan_token(), lex::Tok::CHARLIT('a'));
 }
 
+#[test]
+pub fn test_scan_string_escapes() {
+    let mut lex = lex::Scanner::new(
+        "foo".to_string(),
+        "\"a\\\nb\" \"c\\\r\nd\" \"say \\\"hi\\\"\\n\" \"\\x41\\u{1F600}\" '\\n' '\\x41' x",
+    );
+
+    assert_token_is(lex.scan_token(), lex::Tok::STRINGLIT("ab".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::STRINGLIT("cd".to_string()));
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::STRINGLIT("say \"hi\"\n".to_string()),
+    );
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::STRINGLIT("A\u{1F600}".to_string()),
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::CHARLIT('\n'));
+    assert_token_is(lex.scan_token(), lex::Tok::CHARLIT('A'));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("x".to_string()));
+}
+
+#[test]
+pub fn test_scan_escaped_single_quote() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "'\\'' \"it\\'s\" 'q'");
+
+    assert_token_is(lex.scan_token(), lex::Tok::CHARLIT('\''));
+    assert_token_is(lex.scan_token(), lex::Tok::STRINGLIT("it's".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::CHARLIT('q'));
+}
+
+#[test]
+pub fn test_scan_unterminated_string() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "\"abc");
+    assert_lex_error_at(lex.scan_token(), 1, 1);
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "ok \"abc\\");
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("ok".to_string()));
+    assert!(matches!(lex.scan_token(), Some(Err(_))));
+}
+
+#[test]
+pub fn test_scan_invalid_code_points() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "\"\\u{FFFFFF}\"");
+    assert_lex_error_at(lex.scan_token(), 1, 3);
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "\"\\u{D800}\"");
+    assert_lex_error_at(lex.scan_token(), 1, 3);
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "\"\\u{10FFFF}\\x7f\"");
+    assert!(matches!(lex.scan_token(), Some(Ok(_))));
+}
+
+#[test]
+pub fn test_scan_multiline_strings() {
+    let mut lex = lex::Scanner::new(
+        "foo".to_string(),
+        "\"\"\"{\n  \"key\": 'v'\n}\"\"\" \"\" after",
+    );
+
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::STRINGLIT("{\n  \"key\": 'v'\n}".to_string()),
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::STRINGLIT("".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("after".to_string()));
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "\"\"\"abc\"\"");
+    assert_lex_error_at(lex.scan_token(), 1, 1);
+}
+
+#[test]
+pub fn test_scan_raw_strings() {
+    let mut lex = lex::Scanner::new(
+        "foo".to_string(),
+        "r\"C:\\new\\path\" r#\"say \"hi\"\"# r rx\"\"",
+    );
+
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::STRINGLIT("C:\\new\\path".to_string()),
+    );
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::STRINGLIT("say \"hi\"".to_string()),
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("r".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("rx".to_string()));
+}
+
+#[test]
+pub fn test_scan_unterminated_raw_string() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "x r#\"abc\"");
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("x".to_string()));
+    assert_lex_error_at(lex.scan_token(), 1, 3);
+}
+
+#[test]
+pub fn test_scan_exponent_only_floats() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "6e23 1E5 2e-3 2.5E2 3each 4e");
+
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("6e23".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("1E5".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("2e-3".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("2.5E2".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(3));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("each".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(4));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("e".to_string()));
+}
+
+#[test]
+pub fn test_scan_exponent_signs() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "4.0e+5 4.0e-5 3e+2 1.5e+2");
+
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("4.0e+5".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("4.0e-5".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("3e+2".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("1.5e+2".to_string()));
+    assert_eq!(Some(4.0e-5), lex::float_value("4.0e-5"));
+    assert_eq!(Some(150.0), lex::float_value("1.5e+2"));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_malformed_floats() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "1.0e 1.2.3 2.5e- 7 1.0e");
+
+    assert_lex_error_message(lex.scan_token(), "Malformed float literal");
+    assert_lex_error_at(lex.scan_token(), 1, 6);
+    assert_lex_error_at(lex.scan_token(), 1, 12);
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(7));
+    assert_lex_error_at(lex.scan_token(), 1, 20);
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_trailing_dot() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "3.5 3.foo 3.");
+
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("3.5".to_string()));
+    assert_eq!(
+        (4, lex::Tok::INTLIT(3), 5),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL(".foo".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(3));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL(".".to_string()));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_float_at_eof() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "2.5");
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("2.5".to_string()));
+    assert!(lex.scan_token().is_none());
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "-2.5e3");
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("-2.5e3".to_string()));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_digit_separators() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "1_000_000 1.234_567 2_0.5e1_0 _100");
+
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(1000000));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("1.234567".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FLOATLIT("20.5e10".to_string()));
+    assert_eq!(Some(20.5e10), lex::float_value("2_0.5e1_0"));
+    assert_lex_error_at(lex.scan_token(), 1, 31);
+    assert!(lex.scan_token().is_none());
+
+    // An underscore followed by something other than a digit still
+    // starts a name.
+    let mut lex = lex::Scanner::new("foo".to_string(), "_x1 0b1111_0000 0b1_0 0o7_7");
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("_x1".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(0b1111_0000));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(2));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(0o77));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_bad_digit_separators() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "100_ 1__0 2.5_ 7");
+
+    assert_lex_error_at(lex.scan_token(), 1, 4);
+    assert_lex_error_at(lex.scan_token(), 1, 7);
+    assert_lex_error_at(lex.scan_token(), 1, 14);
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(7));
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "0b1_ 0b_1 0o1__2 9");
+    assert_lex_error_at(lex.scan_token(), 1, 4);
+    assert_lex_error_at(lex.scan_token(), 1, 8);
+    assert_lex_error_at(lex.scan_token(), 1, 14);
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(9));
+}
+
+#[test]
+pub fn test_scan_int_overflow() {
+    let mut lex = lex::Scanner::new(
+        "foo".to_string(),
+        "9223372036854775807 -9223372036854775808 9223372036854775808 -42\n  99999999999999999999",
+    );
+
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(i64::MAX));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(i64::MIN));
+    assert_lex_error_at(lex.scan_token(), 1, 42);
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(-42));
+    assert_lex_error_at(lex.scan_token(), 2, 3);
+    assert!(lex.scan_token().is_none());
+
+    // Only ASCII digits start a number; any other digit is an invalid
+    // character, and scanning moves past it.
+    let mut lex = lex::Scanner::new("foo".to_string(), "\u{0663} foo");
+    assert_lex_error_message(lex.scan_token(), "Invalid token char: \u{0663}");
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_eof() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "foo ").with_eof();
+
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    assert_eq!((4, lex::Tok::EOF, 4), lex.scan_token().unwrap().unwrap());
+    assert!(lex.scan_token().is_none());
+    assert!(lex.scan_token().is_none());
+
+    let mut lex = lex::Scanner::new("foo".to_string(), "foo");
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_line_comments() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "// hi\r\nfoo // there\rbar //\nbaz //");
+
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("bar".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("baz".to_string()));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_doc_comments() {
+    let mut lex = lex::Scanner::new(
+        "foo".to_string(),
+        "/// Adds things.\r\n// not docs\n///\nfun ///last",
+    )
+    .with_doc_comments();
+
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::DOCCOMMENT(" Adds things.".to_string()),
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::DOCCOMMENT("".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::FUN);
+    assert_token_is(lex.scan_token(), lex::Tok::DOCCOMMENT("last".to_string()));
+    assert!(lex.scan_token().is_none());
+
+    // Without the option, doc comments are skipped, so they can appear
+    // in source that's parsed.
+    let mut lex = lex::Scanner::new("foo".to_string(), "/// Adds things.\nfun ///last");
+    assert_token_is(lex.scan_token(), lex::Tok::FUN);
+    assert!(lex.scan_token().is_none());
+    let sect = crate::parse_sect("/// doc\nfun f ( -- ) is end").unwrap();
+    assert_eq!(1, sect.decls.len());
+}
+
+#[test]
+pub fn test_lex_error_offsets() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "foo\n  0o9");
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    match lex.scan_token() {
+        Some(Err(Error::LexicalError {
+            line,
+            column,
+            offset,
+            ..
+        })) => assert_eq!((2, 5, 8), (line, column, offset)),
+        other => panic!("Expected a lexical error, but got {:?}", other),
+    }
+}
+
+#[test]
+pub fn test_tab_aware_columns() {
+    let input = "\tfoo 0o9\n  \tx\t0b";
+
+    let mut lex = lex::Scanner::new("foo".to_string(), input);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    assert_lex_error_at(lex.scan_token(), 1, 15);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("x".to_string()));
+    assert_lex_error_at(lex.scan_token(), 2, 19);
+
+    let mut lex = lex::Scanner::new("foo".to_string(), input).with_tab_width(4);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("foo".to_string()));
+    assert_lex_error_at(lex.scan_token(), 1, 11);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("x".to_string()));
+    assert_eq!((2, 5), lex.line_and_col(12));
+    assert_lex_error_at(lex.scan_token(), 2, 11);
+}
+
+#[test]
+pub fn test_scan_control_chars() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "a\0b \u{7}");
+
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("a".to_string()));
+    assert_lex_error_message(
+        lex.scan_token(),
+        "control character U+0000 is not allowed here",
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("b".to_string()));
+    assert_lex_error_message(
+        lex.scan_token(),
+        "control character U+0007 is not allowed here",
+    );
+}
+
+#[test]
+pub fn test_scan_radix_literals() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "0b1010 0o755 -0b11 0 0o");
+
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(10));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(0o755));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(-3));
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(0));
+    assert_lex_error_at(lex.scan_token(), 1, 24);
+}
+
+#[test]
+pub fn test_scan_bad_radix_literals() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "0o9 0b\n0b102 7");
+
+    assert_lex_error_at(lex.scan_token(), 1, 3);
+    assert_lex_error_at(lex.scan_token(), 1, 7);
+    assert_lex_error_at(lex.scan_token(), 2, 5);
+    assert_token_is(lex.scan_token(), lex::Tok::INTLIT(7));
+}
+
+#[test]
+pub fn test_lenient_scanning() {
+    let input = "a @1 b \"\\q\" c";
+
+    let lenient: Vec<lex::ScannerResult> =
+        lex::Scanner::new_lenient("foo".to_string(), input).collect();
+    assert_eq!(6, lenient.len());
+    assert_eq!(
+        lex::Tok::SYMBOL("a".to_string()),
+        lenient[0].as_ref().unwrap().1
+    );
+    assert!(lenient[1].is_err());
+    // Only the "@" is bad; the "1" after it is a token of its own.
+    assert_eq!(lex::Tok::INTLIT(1), lenient[2].as_ref().unwrap().1);
+    assert_eq!(
+        lex::Tok::SYMBOL("b".to_string()),
+        lenient[3].as_ref().unwrap().1
+    );
+    assert!(lenient[4].is_err());
+    assert_eq!(
+        lex::Tok::SYMBOL("c".to_string()),
+        lenient[5].as_ref().unwrap().1
+    );
+
+    // A valid token directly after a bad character isn't lost.
+    let lenient: Vec<lex::ScannerResult> =
+        lex::Scanner::new_lenient("foo".to_string(), "(\u{1}) foo\u{2}bar").collect();
+    let tokens: Vec<Option<lex::Tok>> = lenient
+        .into_iter()
+        .map(|r| r.ok().map(|(_, t, _)| t))
+        .collect();
+    assert_eq!(
+        vec![
+            Some(lex::Tok::LPAREN),
+            None,
+            Some(lex::Tok::RPAREN),
+            Some(lex::Tok::SYMBOL("foo".to_string())),
+            None,
+            Some(lex::Tok::SYMBOL("bar".to_string())),
+        ],
+        tokens
+    );
+
+    // Without recovery, the tail of each bad token is scanned as junk.
+    let strict: Vec<lex::ScannerResult> = lex::Scanner::new("foo".to_string(), input).collect();
+    assert_eq!(lex::Tok::INTLIT(1), strict[2].as_ref().unwrap().1);
+}
+
+#[test]
+pub fn test_peek_token() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "foo 12").with_eof();
+
+    assert_eq!(
+        (0, lex::Tok::SYMBOL("foo".to_string()), 3),
+        lex.peek_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (0, lex::Tok::SYMBOL("foo".to_string()), 3),
+        lex.peek_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (0, lex::Tok::SYMBOL("foo".to_string()), 3),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (4, lex::Tok::INTLIT(12), 6),
+        lex.peek_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (4, lex::Tok::INTLIT(12), 6),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert_eq!((6, lex::Tok::EOF, 6), lex.peek_token().unwrap().unwrap());
+    assert_eq!((6, lex::Tok::EOF, 6), lex.scan_token().unwrap().unwrap());
+    assert!(lex.peek_token().is_none());
+    assert!(lex.peek_token().is_none());
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_borrowed_tokens() {
+    let input = "fun / @A `t end 12";
+    let mut lex = lex::Scanner::new("foo".to_string(), input);
+    assert_eq!(
+        (0, lex::BorrowedTok::TOKEN(lex::Tok::FUN), 3),
+        lex.scan_borrowed_token().unwrap().unwrap()
+    );
+    // A peeked token can still be taken as a borrowed one.
+    assert_eq!(
+        (4, lex::Tok::SYMBOL("/".to_string()), 5),
+        lex.peek_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (4, lex::BorrowedTok::SYMBOL("/"), 5),
+        lex.scan_borrowed_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (6, lex::BorrowedTok::STACKVAR("@A"), 8),
+        lex.scan_borrowed_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (9, lex::Tok::TYPEVAR("`t".to_string()), 11),
+        lex.scan_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        (12, lex::BorrowedTok::TOKEN(lex::Tok::END), 15),
+        lex.scan_borrowed_token().unwrap().unwrap()
+    );
+    assert_eq!(
+        lex::Tok::INTLIT(12),
+        lex.scan_borrowed_token().unwrap().unwrap().1.into_tok()
+    );
+    assert!(lex.scan_borrowed_token().is_none());
+
+    // Both ways of scanning see the same tokens.
+    let owned: Vec<lex::Tok> = lex::Scanner::new("foo".to_string(), input)
+        .map(|r| r.unwrap().1)
+        .collect();
+    let mut lex = lex::Scanner::new("foo".to_string(), input);
+    let borrowed: Vec<lex::Tok> = std::iter::from_fn(|| lex.scan_borrowed_token())
+        .map(|r| r.unwrap().1.into_tok())
+        .collect();
+    assert_eq!(owned, borrowed);
+}
+
+#[test]
+pub fn test_lexemes() {
+    let input = "foo -12 \"a b\" 'c' 1.5e3 `t [[ ";
+    let mut lex = lex::Scanner::new("foo".to_string(), input);
+    let mut lexemes = Vec::new();
+    while let Some(Ok((start, _, end))) = lex.scan_token() {
+        lexemes.push(lex.lexeme(start, end));
+    }
+    assert_eq!(
+        vec!["foo", "-12", "\"a b\"", "'c'", "1.5e3", "`t", "[["],
+        lexemes
+    );
+}
+
 #[test]
 pub fn test_parse_fun() {
     ast::StackImage::reset_index();
@@ -82,26 +623,33 @@ pub fn test_parse_fun() {
                 ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("int".to_string()))),
                 ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("str".to_string()))),
             ],
+            result_name: None,
         },
         after: ast::StackImage {
             stack_var: ast::Symbol("@_0".to_string()),
             stack: vec![ast::SType::Simple(ast::Identifier::Simple(ast::Symbol(
                 "float".to_string(),
             )))],
+            result_name: None,
         },
+        effect_domains: vec![],
+        span: ast::Span::default(),
     };
     let body: Vec<ast::Expr> = vec![
         ast::Expr::FunCall(ast::FunCallExpr {
             id: ast::Identifier::Simple(ast::Symbol("dup".to_string())),
             type_args: None,
+            span: ast::Span::default(),
         }),
         ast::Expr::FunCall(ast::FunCallExpr {
             id: ast::Identifier::Simple(ast::Symbol("*".to_string())),
             type_args: None,
+            span: ast::Span::default(),
         }),
         ast::Expr::FunCall(ast::FunCallExpr {
             id: ast::Identifier::Simple(ast::Symbol("/".to_string())),
             type_args: None,
+            span: ast::Span::default(),
         }),
     ];
     let expected_fun = ast::FunctionDecl {
@@ -109,6 +657,7 @@ pub fn test_parse_fun() {
         type_params: None,
         signature: se,
         body,
+        span: ast::Span::default(),
     };
 
     let parsed = schism_parser::FunctionDeclParser::new()
@@ -145,24 +694,31 @@ pub fn parse_struct() {
                 stack: vec![ast::SType::Simple(ast::Identifier::Simple(ast::Symbol(
                     "str".to_string(),
                 )))],
+                result_name: None,
             },
             after: ast::StackImage {
                 stack_var: ast::Symbol("@B".to_string()),
                 stack: vec![ast::SType::Simple(ast::Identifier::Simple(ast::Symbol(
                     "str".to_string(),
                 )))],
+                result_name: None,
             },
+            effect_domains: vec![],
+            span: ast::Span::default(),
         },
         body: vec![
             ast::Expr::FunCall(ast::FunCallExpr {
                 id: ast::Identifier::Simple(ast::Symbol("dup".to_string())),
                 type_args: None,
+                span: ast::Span::default(),
             }),
             ast::Expr::FunCall(ast::FunCallExpr {
                 id: ast::Identifier::Simple(ast::Symbol("print".to_string())),
                 type_args: None,
+                span: ast::Span::default(),
             }),
         ],
+        span: ast::Span::default(),
     };
 
     let use_decl = ast::UseDecl {
@@ -176,6 +732,7 @@ pub fn parse_struct() {
             ast::Symbol("b".to_string()),
             ast::Symbol("c".to_string()),
         ]),
+        span: ast::Span::default(),
     };
 
     let m_print = ast::MethodDecl {
@@ -187,11 +744,15 @@ pub fn parse_struct() {
                     ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("str".to_string()))),
                     ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("int".to_string()))),
                 ],
+                result_name: None,
             },
             after: ast::StackImage {
                 stack_var: ast::Symbol("@_0".to_string()),
                 stack: vec![],
+                result_name: None,
             },
+            effect_domains: vec![],
+            span: ast::Span::default(),
         },
         body: vec![
             ast::Expr::Block(block),
@@ -201,8 +762,10 @@ pub fn parse_struct() {
                     ast::Symbol("doNtimes".to_string()),
                 ]),
                 type_args: None,
+                span: ast::Span::default(),
             }),
         ],
+        span: ast::Span::default(),
     };
 
     let init_meth = ast::MethodDecl {
@@ -214,22 +777,29 @@ pub fn parse_struct() {
                     ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("int".to_string()))),
                     ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("str".to_string()))),
                 ],
+                result_name: None,
             },
             after: ast::StackImage {
                 stack_var: ast::Symbol("@A".to_string()),
                 stack: vec![],
+                result_name: None,
             },
+            effect_domains: vec![],
+            span: ast::Span::default(),
         },
         body: vec![
             ast::Expr::FunCall(ast::FunCallExpr {
                 id: ast::Identifier::Simple(ast::Symbol("set-s2!".to_string())),
                 type_args: None,
+                span: ast::Span::default(),
             }),
             ast::Expr::FunCall(ast::FunCallExpr {
                 id: ast::Identifier::Simple(ast::Symbol("set-s1!".to_string())),
                 type_args: None,
+                span: ast::Span::default(),
             }),
         ],
+        span: ast::Span::default(),
     };
 
     let slots = vec![
@@ -252,6 +822,7 @@ pub fn parse_struct() {
         }]),
         fields: slots,
         methods: vec![m_print, init_meth],
+        span: ast::Span::default(),
     };
 
     let sect = ast::Sect {
@@ -293,18 +864,26 @@ pub fn test_parse_harder_fun() {
                         stack: vec![ast::SType::Simple(ast::Identifier::Simple(ast::Symbol(
                             "int".to_string(),
                         )))],
+                        result_name: None,
                     },
                     after: ast::StackImage {
                         stack_var: ast::Symbol("@B".to_string()),
                         stack: vec![],
+                        result_name: None,
                     },
+                    effect_domains: vec![],
+                    span: ast::Span::default(),
                 }),
             ],
+            result_name: None,
         },
         after: ast::StackImage {
             stack_var: ast::Symbol("@B".to_string()),
             stack: vec![],
+            result_name: None,
         },
+        effect_domains: vec![],
+        span: ast::Span::default(),
     };
 
     let body: Vec<ast::Expr> = vec![
@@ -313,14 +892,17 @@ pub fn test_parse_harder_fun() {
             type_args: Some(vec![ast::SType::Simple(ast::Identifier::Simple(
                 ast::Symbol("int".to_string()),
             ))]),
+            span: ast::Span::default(),
         }),
         ast::Expr::FunCall(ast::FunCallExpr {
             id: ast::Identifier::Simple(ast::Symbol("swap".to_string())),
             type_args: None,
+            span: ast::Span::default(),
         }),
         ast::Expr::FunCall(ast::FunCallExpr {
             id: ast::Identifier::Simple(ast::Symbol("apply".to_string())),
             type_args: None,
+            span: ast::Span::default(),
         }),
     ];
     let expected_fun = ast::FunctionDecl {
@@ -328,6 +910,7 @@ pub fn test_parse_harder_fun() {
         type_params: None,
         signature: se,
         body,
+        span: ast::Span::default(),
     };
 
     let parsed = schism_parser::FunctionDeclParser::new()
@@ -410,3 +993,1240 @@ pub fn test_parse_lots_of_stuff() {
     let parsed_str = parsed.unwrap().to_string();
     assert_eq!(expected, parsed_str);
 }
+
+#[test]
+pub fn test_parse_bool_literals() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "true false truest");
+    assert_token_is(lex.scan_token(), lex::Tok::TRUE);
+    assert_token_is(lex.scan_token(), lex::Tok::FALSE);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("truest".to_string()));
+
+    let funstr = "fun both ( @A -- @A bool bool ) is true false end";
+    let parsed = schism_parser::FunctionDeclParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), funstr))
+        .unwrap();
+    assert_eq!(
+        vec![
+            ast::Expr::BoolLit(true, ast::Span::new(35, 39)),
+            ast::Expr::BoolLit(false, ast::Span::new(40, 45))
+        ],
+        parsed.body
+    );
+    assert_eq!(
+        "   fun both(@A  -- @A bool bool) is\n      true\n      false\n   end\n",
+        parsed.to_string()
+    );
+}
+
+#[test]
+pub fn test_parse_alias() {
+    let source = "
+    alias [`a] Dict is [str, `a]Map end
+    alias Ints is [int]List end
+    ";
+    let parsed = schism_parser::SectParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), source))
+        .unwrap();
+
+    let ast::Decl::Alias(dict) = &parsed.decls[0] else {
+        panic!("Expected an alias declaration")
+    };
+    assert_eq!(ast::Symbol("Dict".to_string()), dict.name);
+    assert_eq!(
+        Some(vec![ast::TypeParam {
+            name: ast::Symbol("`a".to_string()),
+            constraint: None,
+        }]),
+        dict.type_params
+    );
+    assert_eq!(
+        ast::SType::Parametric(
+            vec![
+                ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("str".to_string()))),
+                ast::SType::TypeVar(ast::Symbol("`a".to_string())),
+            ],
+            ast::Identifier::Simple(ast::Symbol("Map".to_string())),
+        ),
+        dict.aliased
+    );
+
+    let expected = "   sect
+      alias [`a]Dict is [str, `a]Map end
+      alias Ints is [int]List end
+   end
+";
+    assert_eq!(expected, parsed.to_string());
+}
+
+#[test]
+pub fn test_parse_const() {
+    let source = "
+    const max_size: int is 1024 end
+    const greeting: str is \"hello\" end
+    ";
+    let parsed = schism_parser::SectParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), source))
+        .unwrap();
+
+    let ast::Decl::Const(max_size) = &parsed.decls[0] else {
+        panic!("Expected a const declaration")
+    };
+    assert_eq!(ast::Symbol("max_size".to_string()), max_size.name);
+    assert_eq!(
+        ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("int".to_string()))),
+        max_size.value_type
+    );
+    assert!(matches!(max_size.body[..], [ast::Expr::IntLit(1024, _)]));
+
+    let expected = "   sect
+      const max_size: int is
+         1024
+      end
+      const greeting: str is
+         \"hello\"
+      end
+   end
+";
+    assert_eq!(expected, parsed.to_string());
+}
+
+#[test]
+pub fn test_parse_spans() {
+    let funstr = "fun foo ( -- ) is 12 [int]bar \"s\" end";
+    let parsed = schism_parser::DeclParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), funstr))
+        .unwrap();
+    assert_eq!(ast::Span::new(0, funstr.len()), parsed.span());
+
+    let ast::Decl::Function(f) = parsed else {
+        panic!("Expected a function declaration")
+    };
+    let spans: Vec<ast::Span> = f.body.iter().map(|e| e.span()).collect();
+    assert_eq!(
+        vec![
+            ast::Span::new(18, 20),
+            ast::Span::new(21, 29),
+            ast::Span::new(30, 33)
+        ],
+        spans
+    );
+}
+
+struct CallCounter {
+    calls: usize,
+    types: usize,
+}
+
+impl Visitor for CallCounter {
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        if let ast::Expr::FunCall(_) = expr {
+            self.calls += 1;
+        }
+        visit::walk_expr(self, expr)
+    }
+
+    fn visit_type(&mut self, s_type: &ast::SType) {
+        self.types += 1;
+        visit::walk_type(self, s_type)
+    }
+}
+
+#[test]
+pub fn test_visitor() {
+    let source = "
+    struct Pair is
+        slot a: Int
+        meth swap ( -- ) is
+            dup if drop else [[ ( Int -- ) print ]] end
+        end
+    end
+    fun twice ( [Int]List -- ) is
+        #[ Int | 1 dup, 2 ]# loop bump end
+    end
+    ";
+    let sect = schism_parser::SectParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), source))
+        .unwrap();
+    let mut counter = CallCounter { calls: 0, types: 0 };
+    counter.visit_sect(&sect);
+    assert_eq!(5, counter.calls);
+    assert_eq!(5, counter.types);
+}
+
+fn mk_expected_obj() -> Twist {
+    return crate::twist!(obj Function {
+        attr name = "foo";
+        arr type_params {}
+        val doc {}
+        val effect {
+            obj StackEffect {
+                arr before {
+                    attr SimpleType = "Int";
+                }
+            }
+        }
+    });
+}
+
+#[test]
+pub fn test_twist_to_json() {
+    let expected = concat!(
+        r#"{"kind":"obj","name":"Function","children":["#,
+        r#"{"kind":"attr","name":"name","value":"foo"},"#,
+        r#"{"kind":"arr","name":"type_params","children":[]},"#,
+        r#"{"kind":"value","name":"doc","value":null},"#,
+        r#"{"kind":"value","name":"effect","value":"#,
+        r#"{"kind":"obj","name":"StackEffect","children":["#,
+        r#"{"kind":"arr","name":"before","children":["#,
+        r#"{"kind":"attr","name":"SimpleType","value":"Int"}]}]}}]}"#
+    );
+    assert_eq!(expected, mk_expected_obj().to_json());
+    assert_eq!(
+        r#"{"kind":"attr","name":"s","value":"a\"b\n"}"#,
+        Twist::attr("s", "a\"b\n".to_string()).to_json()
+    );
+}
+
+#[test]
+pub fn test_twist_to_dot() {
+    let dot = mk_expected_obj().to_dot();
+    assert!(dot.starts_with("digraph twist {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("n0 [shape=box, label=\"obj Function\"];"));
+    assert!(dot.contains("n1 [shape=ellipse, label=\"name=foo\"];"));
+    assert!(dot.contains("n2 [shape=box, label=\"arr type_params\"];"));
+    assert!(dot.contains("n3 [shape=plaintext, label=\"doc\"];"));
+    assert!(dot.contains("n5 [shape=box, label=\"obj StackEffect\"];"));
+    assert!(dot.contains("n7 [shape=ellipse, label=\"SimpleType=Int\"];"));
+    assert!(dot.contains("n0 -> n4;"));
+    assert!(dot.contains("n4 -> n5;"));
+    assert!(dot.contains("n6 -> n7;"));
+}
+
+#[test]
+pub fn test_twist_diff() {
+    let expected = mk_expected_obj();
+    assert!(expected.diff(&mk_expected_obj()).is_none());
+
+    let changed = Twist::obj(
+        "Function",
+        vec![
+            Twist::attr("name", "foo".to_string()),
+            Twist::arr("type_params", vec![]),
+            Twist::opt_val("doc", None),
+            Twist::val(
+                "effect",
+                Twist::obj(
+                    "StackEffect",
+                    vec![Twist::arr(
+                        "before",
+                        vec![Twist::attr("TypeVar", "`X".to_string())],
+                    )],
+                ),
+            ),
+        ],
+    );
+    assert_eq!(
+        Some(
+            "Function/effect/StackEffect/before[0]: expected attr SimpleType='Int', found attr TypeVar='`X'"
+                .to_string()
+        ),
+        expected.diff(&changed)
+    );
+
+    let shorter = Twist::obj("Function", vec![Twist::attr("name", "foo".to_string())]);
+    assert_eq!(
+        Some("Function: expected 4 children, found 1".to_string()),
+        expected.diff(&shorter)
+    );
+}
+
+#[test]
+pub fn test_twist_code_escapes_values() {
+    let mut code = String::new();
+    Twist::attr("StringLit", "a\"b\\c\n\t".to_string()).code(&mut code);
+    assert_eq!(
+        r#"Twist::attr("StringLit", "a\"b\\c\n\t".to_string())"#,
+        code
+    );
+}
+
+#[test]
+pub fn test_twist_code_keeps_empty_values() {
+    let t = Twist::obj(
+        "TypeParam",
+        vec![
+            Twist::attr("name", "`T".to_string()),
+            Twist::opt_val("constraint", None),
+        ],
+    );
+    let mut code = String::new();
+    t.code(&mut code);
+    assert_eq!(
+        r#"Twist::obj("TypeParam", vec![Twist::attr("name", "`T".to_string()), Twist::opt_val("constraint", None)])"#,
+        code
+    );
+}
+
+#[test]
+pub fn test_twist_render_escapes_values() {
+    let t = Twist::obj(
+        "Exprs",
+        vec![
+            Twist::attr("CharLit", "\n".to_string()),
+            Twist::attr("StringLit", "it's \\ \u{7}".to_string()),
+        ],
+    );
+    let expected = "obj Exprs:
+   attr CharLit='\\n'
+   attr StringLit='it\\'s \\\\ \\u{7}'
+";
+    let mut rendered = String::new();
+    t.render(&mut rendered, 0);
+    assert_eq!(expected, rendered);
+    assert_eq!(Ok(t), Twist::parse(&rendered));
+}
+
+#[test]
+pub fn test_twist_parse() {
+    let t = mk_expected_obj();
+    assert_eq!(Ok(t.clone()), Twist::parse(&t.to_string()));
+
+    assert!(t
+        .to_string()
+        .starts_with("obj Function:\n   attr name='foo'\n"));
+
+    let mut indented = String::new();
+    t.render(&mut indented, 1);
+    assert_eq!(Ok(t), Twist::parse(&indented));
+
+    assert!(Twist::parse("").is_err());
+    assert!(Twist::parse("   frob x:\n").is_err());
+    assert!(Twist::parse("   obj x:\n         attr y='z'\n").is_err());
+}
+
+#[test]
+pub fn test_twist_identifiers() {
+    let parsed = schism_parser::IdentParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), "lib::blob::squid"))
+        .unwrap();
+    assert_eq!(
+        crate::twist!(obj ScopedName {
+            attr path = "lib::blob";
+            attr name = "squid";
+        }),
+        parsed.twist()
+    );
+
+    let parsed = schism_parser::IdentParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), "squid"))
+        .unwrap();
+    assert_eq!(
+        crate::twist!(obj LocalName {
+            attr name = "squid";
+        }),
+        parsed.twist()
+    );
+}
+
+#[test]
+pub fn test_render_pretty_errors() {
+    let source = "fun f ( -- ) is\n\t\"abc\nend\n";
+    let mut lex = lex::Scanner::new("foo.schism".to_string(), source);
+    let err = lex.find_map(|r| r.err()).unwrap();
+    assert_eq!("foo.schism", err.source());
+    let expected = "error: Unterminated string literal
+ --> foo.schism:2:9
+  |
+2 | \t\"abc
+  | \t^
+";
+    assert_eq!(expected, err.render_pretty(source, false));
+
+    let colored = err.render_pretty(source, true);
+    assert!(colored.contains("\x1b[1;31merror\x1b[0m: Unterminated string literal"));
+}
+
+#[test]
+pub fn test_parse_error_suggestions() {
+    let source = "strcut Foo is end";
+    let err = schism_parser::SectParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), source))
+        .unwrap_err();
+    let err = Error::from_parse_error("foo", source, err);
+    match &err {
+        Error::ParseError {
+            line,
+            column,
+            message,
+            ..
+        } => {
+            assert_eq!((1, 1), (*line, *column));
+            assert!(message.ends_with("; did you mean 'struct'?"), "{}", message);
+        }
+        other => panic!("Expected a parse error, but got {:?}", other),
+    }
+
+    let source = "fun f ( -- ) is dup zzz";
+    let err = schism_parser::SectParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), source))
+        .unwrap_err();
+    let err = Error::from_parse_error("foo", source, err);
+    assert!(matches!(err, Error::ParseError { offset: 23, .. }));
+    assert!(!format!("{:?}", err).contains("did you mean"));
+}
+
+#[test]
+pub fn test_symbol_table() {
+    let source = "
+    struct Point is
+        slot x: int
+    end
+    fun Point ( int int -- Point ) is make end
+    alias Points is [Point]List end
+    var origin: Point init 0 0 Point end
+    ";
+    let sect = schism_parser::SectParser::new()
+        .parse(lex::Scanner::new("foo".to_string(), source))
+        .unwrap();
+    let table = crate::symbols::SymbolTable::new(&sect);
+
+    assert!(matches!(
+        table.lookup_type("Point"),
+        Some(ast::Decl::Struct(_))
+    ));
+    assert!(matches!(
+        table.lookup_value("Point"),
+        Some(ast::Decl::Function(_))
+    ));
+    assert!(matches!(
+        table.lookup_type("Points"),
+        Some(ast::Decl::Alias(_))
+    ));
+    assert!(matches!(
+        table.lookup_value("origin"),
+        Some(ast::Decl::Var(_))
+    ));
+    assert!(table.lookup_type("origin").is_none());
+    assert!(table.lookup_value("Points").is_none());
+    assert!(table.lookup_value("nowhere").is_none());
+}
+
+#[test]
+pub fn test_check_arity() {
+    let mut prims = std::collections::HashMap::new();
+    for (name, effect) in [("+", "( int int -- int )"), ("dup", "( int -- int int )")] {
+        prims.insert(
+            name.to_string(),
+            schism_parser::StackEffectParser::new()
+                .parse(lex::Scanner::new("prims".to_string(), effect))
+                .unwrap(),
+        );
+    }
+    let parse_fun = |source| {
+        schism_parser::FunctionDeclParser::new()
+            .parse(lex::Scanner::new("foo".to_string(), source))
+            .unwrap()
+    };
+
+    let balanced = parse_fun("fun f ( int -- int ) is 2 + dup + end");
+    assert_eq!(Ok(()), crate::effects::check_arity(&balanced, &prims));
+
+    let underflow = parse_fun("fun g ( -- int ) is 1 + end");
+    let err = crate::effects::check_arity(&underflow, &prims).unwrap_err();
+    assert_eq!(crate::diagnostic::Severity::Error, err.severity);
+    assert_eq!(ast::Span::new(22, 23), err.span);
+    assert_eq!(
+        "'+' needs 2 values on the stack, but only 1 are available",
+        err.message
+    );
+
+    let unknown = parse_fun("fun h ( -- int ) is mystery + end");
+    assert_eq!(Ok(()), crate::effects::check_arity(&unknown, &prims));
+}
+
+#[test]
+pub fn test_fold_constants() {
+    let fold = |source| {
+        let mut f = schism_parser::FunctionDeclParser::new()
+            .parse(lex::Scanner::new("foo".to_string(), source))
+            .unwrap();
+        assert!(crate::fold::fold_constants(&mut f.body).is_empty());
+        return f.body;
+    };
+
+    assert_eq!(
+        vec![ast::Expr::IntLit(7, ast::Span::new(20, 25))],
+        fold("fun f ( -- int ) is 3 4 + end")
+    );
+    assert!(matches!(
+        fold("fun f ( -- int ) is 1 2 + 3 * 10 swap - end")[..],
+        [
+            ast::Expr::IntLit(9, _),
+            ast::Expr::IntLit(10, _),
+            ast::Expr::FunCall(_),
+            ast::Expr::FunCall(_)
+        ]
+    ));
+    assert!(matches!(
+        fold("fun f ( -- int int ) is 5 2 8 3 - * end")[..],
+        [ast::Expr::IntLit(5, _), ast::Expr::IntLit(10, _)]
+    ));
+
+    // A division by zero is left for the runtime, with a warning. The
+    // zero can itself be the result of folding.
+    let mut f = crate::parse_decl("fun f ( -- int ) is 6 0 / 6 1 1 - / end").unwrap();
+    let ast::Decl::Function(f) = &mut f else {
+        panic!("Expected a function declaration")
+    };
+    let diagnostics = crate::fold::fold_constants(&mut f.body);
+    assert!(matches!(
+        f.body[..],
+        [
+            ast::Expr::IntLit(6, _),
+            ast::Expr::IntLit(0, _),
+            ast::Expr::FunCall(_),
+            ast::Expr::IntLit(6, _),
+            ast::Expr::IntLit(0, _),
+            ast::Expr::FunCall(_)
+        ]
+    ));
+    assert_eq!(
+        vec![
+            crate::diagnostic::Diagnostic::warning(
+                ast::Span::new(20, 25),
+                "6 is divided by zero".to_string()
+            ),
+            crate::diagnostic::Diagnostic::warning(
+                ast::Span::new(26, 35),
+                "6 is divided by zero".to_string()
+            ),
+        ],
+        diagnostics
+    );
+}
+
+#[test]
+pub fn test_to_source_round_trip() {
+    use crate::format::ToSource;
+
+    let source = "
+    use lib::blob{that, +}
+    alias [`a] Dict is [str, `a]Map end
+    const limit: float is -2.5e3 end
+
+    struct [`a, `b << Ord] Squortle ( that ) is
+        slot foo: [int, `a]List
+        meth m ( int -- str) is
+           + <- frob
+           if 'x' '\\'' else [[ ( @A -- @A ) \"a \\\"b\\\"\\n\" print]] end
+        end
+    end
+
+    var q: [int, str]Squortle init
+       #[ int | 1 2, 3 ]# #{ str, int | (\"a\", 1), (\"b\" x, 2) }# true
+    end
+
+    fun [`t] meta ( @A int (@A int -- @B) --  @B) is
+        [int]twiddle loop swap::it apply end 4.0
+    end
+
+    fun nested ( [ [int]List ]Map -- ) is [ [int]List ]g end
+    ";
+    let parse = |text: &str| {
+        ast::StackImage::reset_index();
+        schism_parser::SectParser::new()
+            .parse(lex::Scanner::new("foo".to_string(), text))
+            .unwrap()
+    };
+
+    let original = parse(source);
+    let formatted = original.to_source();
+    let reparsed = parse(&formatted);
+    assert_eq!(original.to_string(), reparsed.to_string());
+    assert_eq!(formatted, reparsed.to_source());
+
+    let expected = "
+fun [`t] meta ( @A int ( @A int -- @B ) -- @B ) is
+   [int]twiddle
+   loop
+      swap::it
+      apply
+   end
+   4.0
+end
+
+fun nested ( [ [int]List]Map -- ) is
+   [ [int]List]g
+end
+";
+    assert!(formatted.ends_with(expected), "{}", formatted);
+}
+
+#[test]
+pub fn test_parse_helpers() {
+    ast::StackImage::reset_index();
+    let parsed = crate::parse_decl("fun foo ( int str -- float ) is dup * / end").unwrap();
+    assert_eq!(
+        "   fun foo(@_0 int str -- @_0 float) is\n      dup\n      *\n      /\n   end\n",
+        parsed.to_string()
+    );
+
+    let sect = crate::parse_sect("use a::b\nvar x: int init 1 end").unwrap();
+    assert_eq!(1, sect.uses.len());
+    assert_eq!(1, sect.decls.len());
+
+    assert!(matches!(
+        crate::parse_expr("[[ ( -- int ) 3 ]]"),
+        Ok(ast::Expr::Block(_))
+    ));
+
+    match crate::parse_sect("fun foo ( -- ) is") {
+        Err(Error::ParseError { source, offset, .. }) => {
+            assert_eq!("<string>", source);
+            assert_eq!(17, offset);
+        }
+        other => panic!("Expected a parse error, but got {:?}", other),
+    }
+}
+
+#[test]
+pub fn test_parse_error_expected() {
+    match crate::parse_decl("var x: int end") {
+        Err(Error::ParseError {
+            expected, message, ..
+        }) => {
+            assert_eq!(vec!["init"], expected);
+            assert_eq!("Unexpected token 'end'; expected one of init", message);
+        }
+        other => panic!("Expected a parse error, but got {:?}", other),
+    }
+
+    match crate::parse_decl("var x: int init") {
+        Err(Error::ParseError { expected, .. }) => {
+            assert!(expected.contains(&"integer literal".to_string()));
+            assert!(expected.contains(&"symbol".to_string()));
+            assert!(expected.iter().all(|e| !e.contains('"')));
+        }
+        other => panic!("Expected a parse error, but got {:?}", other),
+    }
+}
+
+#[test]
+pub fn test_unused_import_warnings() {
+    let sect = crate::parse_sect(
+        "use lib::blob { that, +, Thing, unused }
+        use lib::whole
+        fun f ( Thing -- ) is 1 2 + that end",
+    )
+    .unwrap();
+    let diagnostics = crate::lint::check_unused_imports(&sect);
+    assert_eq!(
+        vec![crate::diagnostic::Diagnostic::warning(
+            ast::Span::new(0, 40),
+            "'unused' is imported but never used".to_string()
+        )],
+        diagnostics
+    );
+    assert!(!crate::diagnostic::has_errors(&diagnostics));
+}
+
+#[test]
+pub fn test_float_text_preserved() {
+    // The text of a float survives as written, except that digit
+    // separators are dropped by the scanner.
+    let parsed = crate::parse_decl("const big: float is -4.0e5 1_000.5 end").unwrap();
+    assert_eq!(
+        "   const big: float is\n      -4.0e5\n      1000.5\n   end\n",
+        parsed.to_string()
+    );
+    assert_eq!(
+        "const big: float is\n   -4.0e5\n   1000.5\nend\n",
+        crate::format::ToSource::to_source(&parsed)
+    );
+}
+
+#[test]
+pub fn test_arrow_is_reserved() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "-> --> ->x");
+    assert_token_is(lex.scan_token(), lex::Tok::ARROW);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("-->".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("->x".to_string()));
+}
+
+#[test]
+pub fn test_parse_named_result() {
+    let decl = crate::parse_decl("fun add ( int int -- sum -> int ) is + end").unwrap();
+    let ast::Decl::Function(f) = decl else {
+        panic!("Expected a function declaration")
+    };
+    assert_eq!(None, f.signature.before.result_name);
+    assert_eq!(
+        Some(ast::Symbol("sum".to_string())),
+        f.signature.after.result_name
+    );
+    assert_eq!(
+        crate::twist!(obj StackImage {
+            attr context = "@_0";
+            attr result_name = "sum";
+            arr stack {
+                obj SimpleType {
+                    val name {
+                        obj LocalName {
+                            attr name = "int";
+                        }
+                    }
+                }
+            }
+        }),
+        f.signature.after.twist()
+    );
+    assert_eq!(
+        "fun add ( int int -- sum -> int ) is\n   +\nend\n",
+        crate::format::ToSource::to_source(&ast::Decl::Function(f))
+    );
+
+    // Only a single result can be named, and only in a stack effect.
+    for bad in [
+        "fun f ( -- a -> int int ) is end",
+        "fun f ( a -> int -- ) is end",
+        "fun f ( -- ) is -> end",
+    ] {
+        match crate::parse_decl(bad) {
+            Err(Error::ParseError { .. }) => (),
+            other => panic!("Expected a parse error for {}, but got {:?}", bad, other),
+        }
+    }
+}
+
+#[test]
+pub fn test_resolve_locals() {
+    let decl = crate::parse_decl(
+        "fun f ( int int -- int ) is
+            local x
+            x y
+            if local y y x else y end
+            [[ ( -- int ) x ]]
+        end",
+    )
+    .unwrap();
+    let ast::Decl::Function(mut f) = decl else {
+        panic!("Expected a function declaration")
+    };
+    assert!(matches!(f.body[1], ast::Expr::FunCall(_)));
+
+    crate::resolve::resolve_locals(&mut f.body);
+    let expected = "   fun f(@_0 int int -- @_0 int) is
+      local x
+      localref x
+      y
+      if
+         local y
+         localref y
+         localref x
+      else
+         y
+      end
+      [[
+         (@_1  -- @_1 int)
+         localref x
+      ]]
+   end
+";
+    assert_eq!(expected, f.to_string());
+}
+
+#[test]
+pub fn test_method_sends() {
+    let sect = crate::parse_sect(
+        "struct Counter is
+            slot count: int
+            meth bump ( -- ) is count 1 + end
+        end
+        fun f ( Counter -- ) is <- bump bump if <- reset else 0 end end",
+    )
+    .unwrap();
+    let ast::Decl::Function(f) = &sect.decls[1] else {
+        panic!("Expected a function declaration")
+    };
+    // There's only one way to send a message: "<- name" sends it to
+    // the value on top of the stack, and the method is picked by the
+    // receiver's type at runtime. There's no separate static dispatch
+    // form; a bare name is always a function call, even when a
+    // method has the same name.
+    assert!(matches!(&f.body[0], ast::Expr::MethodCall(m) if m.sym.0 == "bump"));
+    assert!(matches!(&f.body[1], ast::Expr::FunCall(c)
+        if c.id == ast::Identifier::Simple(ast::Symbol("bump".to_string()))));
+    assert_eq!("   <- bump\n", f.body[0].to_string());
+
+    let diagnostics = crate::lint::check_method_sends(&sect);
+    assert_eq!(1, diagnostics.len());
+    assert_eq!("no struct defines a method 'reset'", diagnostics[0].message);
+    assert_eq!(ast::Span::new(152, 160), diagnostics[0].span);
+}
+
+#[test]
+pub fn test_parse_unicode_char_escapes() {
+    let decl = crate::parse_decl("fun f ( -- char char char ) is '\\u{41}' '\\u{1F600}' '😀' end")
+        .unwrap();
+    let ast::Decl::Function(f) = decl else {
+        panic!("Expected a function declaration")
+    };
+    assert_eq!(
+        vec![
+            ast::Expr::CharLit('A', ast::Span::new(31, 39)),
+            ast::Expr::CharLit('😀', ast::Span::new(40, 51)),
+            ast::Expr::CharLit('😀', ast::Span::new(52, 58)),
+        ],
+        f.body
+    );
+    assert_eq!("   'A'\n", f.body[0].to_string());
+}
+
+#[test]
+pub fn test_parse_effect_domains() {
+    let decl = crate::parse_decl(
+        "fun log ( @A str -- @A ) uses IO, sys::State is
+            [[ ( -- ) uses IO \"hi\" print ]]
+            drop
+        end",
+    )
+    .unwrap();
+    let ast::Decl::Function(f) = &decl else {
+        panic!("Expected a function declaration")
+    };
+    assert_eq!(
+        vec![
+            ast::Identifier::Simple(ast::Symbol("IO".to_string())),
+            ast::Identifier::Qualified(vec![
+                ast::Symbol("sys".to_string()),
+                ast::Symbol("State".to_string())
+            ]),
+        ],
+        f.signature.effect_domains
+    );
+    let ast::Expr::Block(b) = &f.body[0] else {
+        panic!("Expected a block")
+    };
+    assert_eq!(1, b.effect.effect_domains.len());
+    assert!(decl
+        .to_string()
+        .starts_with("   fun log(@A str -- @A ) uses IO, sys::State is\n"));
+
+    let plain = crate::parse_decl("fun f ( -- ) is end").unwrap();
+    let ast::Decl::Function(f) = &plain else {
+        panic!("Expected a function declaration")
+    };
+    assert!(f.signature.effect_domains.is_empty());
+}
+
+#[test]
+pub fn test_check_contexts() {
+    let effect = |s: &str| match crate::parse_decl(s).unwrap() {
+        ast::Decl::Function(f) => f.signature,
+        _ => panic!("Expected a function declaration"),
+    };
+    let check = |s: &str| crate::effects::check_contexts(&effect(s)).map_err(|e| e.span);
+
+    assert_eq!(Ok(()), check("fun f ( int -- int ) is end"));
+    assert_eq!(
+        Ok(()),
+        check("fun f ( @A int ( @A int -- @B ) -- @B ) is end")
+    );
+    assert_eq!(Ok(()), check("fun f ( @A -- @A ( @B -- @A ) ) is end"));
+    assert_eq!(
+        Err(ast::Span::new(6, 22)),
+        check("fun f ( @A int -- @C ) is end")
+    );
+    // The error points at the function type that uses @C, not at
+    // the whole signature.
+    assert_eq!(
+        Err(ast::Span::new(17, 29)),
+        check("fun f ( @A -- @A ( @B -- @C ) ) is end")
+    );
+
+    let err = crate::effects::check_contexts(&effect("fun f ( @A int -- @C ) is end")).unwrap_err();
+    assert_eq!(crate::diagnostic::Severity::Error, err.severity);
+    assert_eq!(
+        "context @C in the result of a stack effect isn't bound by its inputs",
+        err.message
+    );
+}
+
+#[test]
+pub fn test_error_to_json() {
+    let err = crate::parse_decl("var x:\n    int end").unwrap_err();
+    assert_eq!(
+        r#"{"severity": "error", "message": "Unexpected token 'end'; expected one of init", "file": "<string>", "line": 2, "column": 9, "expected": ["init"]}"#,
+        err.to_json()
+    );
+}
+
+#[test]
+pub fn test_parse_error_expected_is_sorted() {
+    let text = "fun f ( -- ) is 1 ]] end";
+    let message = |err: Error| match err {
+        Error::ParseError {
+            message, expected, ..
+        } => (message, expected),
+        e => panic!("Expected a parse error, got {:?}", e),
+    };
+    let (first, expected) = message(crate::parse_decl(text).unwrap_err());
+    let (second, _) = message(crate::parse_decl(text).unwrap_err());
+    assert_eq!(first, second);
+
+    let mut sorted = expected.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted, expected);
+    assert_eq!(
+        vec!["float literal", "integer literal"],
+        crate::error::describe_expected(&[
+            "INTLIT".to_string(),
+            "FLOATLIT".to_string(),
+            "INTLIT".to_string()
+        ])
+    );
+}
+
+#[test]
+pub fn test_parse_recovering() {
+    let text = "use lib::io
+fun a ( int -- ) 1 end
+fun b ( -- ) is
+    1 ]] 2
+end
+fun c ( -- int ) is 3 end
+fun d ( -- ) is
+    dup
+";
+    let (sect, errors) = crate::parse_recovering(text);
+    assert_eq!(1, sect.uses.len());
+    assert_eq!(1, sect.decls.len());
+    assert!(matches!(&sect.decls[0], ast::Decl::Function(f) if f.name.0 == "c"));
+
+    let locations: Vec<(usize, usize)> = errors
+        .iter()
+        .map(|e| match e {
+            Error::ParseError { line, column, .. } => (*line, *column),
+            e => panic!("Expected a parse error, got {:?}", e),
+        })
+        .collect();
+    assert_eq!(vec![(2, 18), (4, 7), (8, 8)], locations);
+    // The stray "]]" is reported the way parse_sect would report it.
+    match &errors[1] {
+        Error::ParseError { message, .. } => {
+            assert_eq!("Unexpected ']]' with nothing open to close", message)
+        }
+        e => panic!("Expected a parse error, got {:?}", e),
+    }
+
+    let (sect, errors) =
+        crate::parse_recovering(text.replace("is\n    dup", "is dup end").as_str());
+    assert_eq!(2, sect.decls.len());
+    assert_eq!(2, errors.len());
+
+    // As in parse_sect, all of the uses have to come first; and a
+    // lexical error is reported without losing the declaration.
+    let (sect, errors) = crate::parse_recovering(
+        "fun a ( -- ) is 1 end
+use lib::io
+fun b ( -- ) is 2 \u{1} end",
+    );
+    assert_eq!(0, sect.uses.len());
+    assert_eq!(2, sect.decls.len());
+    let offsets: Vec<usize> = errors.iter().map(|e| e.offset()).collect();
+    assert_eq!(vec![22, 52], offsets);
+}
+
+#[test]
+pub fn test_parse_qualified_calls() {
+    let parsed = crate::parse_expr("lib::blob::that").unwrap();
+    let ast::Expr::FunCall(call) = &parsed else {
+        panic!("Expected a function call")
+    };
+    assert_eq!(ast::Span::new(0, 15), call.span);
+    assert_eq!(
+        crate::twist!(obj ScopedName {
+            attr path = "lib::blob";
+            attr name = "that";
+        }),
+        call.id.twist()
+    );
+
+    // Qualified names are separated by "::"; a "." is just another
+    // character in a symbol, so this is a call to one local name.
+    let parsed = crate::parse_expr("lib.blob.that").unwrap();
+    let ast::Expr::FunCall(call) = &parsed else {
+        panic!("Expected a function call")
+    };
+    assert_eq!(
+        ast::Identifier::Simple(ast::Symbol("lib.blob.that".to_string())),
+        call.id
+    );
+}
+
+#[test]
+pub fn test_parse_call_type_args() {
+    let parsed = crate::parse_expr("[int, [`T]List]foo").unwrap();
+    let ast::Expr::FunCall(call) = &parsed else {
+        panic!("Expected a function call")
+    };
+    assert_eq!(
+        Some(vec![
+            ast::SType::Simple(ast::Identifier::Simple(ast::Symbol("int".to_string()))),
+            ast::SType::Parametric(
+                vec![ast::SType::TypeVar(ast::Symbol("`T".to_string()))],
+                ast::Identifier::Simple(ast::Symbol("List".to_string()))
+            ),
+        ]),
+        call.type_args
+    );
+    assert_eq!(
+        "[int, [`T]List]foo",
+        crate::format::ToSource::to_source(&parsed)
+    );
+    assert_eq!("   [int, [`T]List]foo\n", parsed.to_string());
+}
+
+#[test]
+pub fn test_parse_trailing_commas() {
+    let render = |text: &str| {
+        ast::StackImage::reset_index();
+        crate::parse_sect(text).unwrap().to_string()
+    };
+    assert_eq!(
+        render(
+            "use lib::blob { a, b, c }
+            struct [`a, `b] Pair ( Thing, Other ) is slot x: int end"
+        ),
+        render(
+            "use lib::blob { a, b, c, }
+            struct [`a, `b,] Pair ( Thing, Other, ) is slot x: int end"
+        )
+    );
+    assert_eq!(
+        render("fun f ( -- ) is [int, str]g #[ int | 1, 2 ]# end"),
+        render("fun f ( -- ) is [int, str,]g #[ int | 1, 2, ]# end")
+    );
+    assert!(crate::parse_sect("use lib::blob { , }").is_err());
+    assert!(crate::parse_sect("use lib::blob { a,, }").is_err());
+}
+
+#[test]
+pub fn test_parse_empty_sect() {
+    for text in ["", "   \n\t", "// just a comment\n", "use lib::io\n"] {
+        let sect = crate::parse_sect(text).unwrap();
+        assert!(sect.decls.is_empty(), "{:?}", text);
+    }
+    assert!(crate::parse_sect("").unwrap().uses.is_empty());
+}
+
+#[test]
+pub fn test_twist_render_with() {
+    let t = crate::twist!(obj Function {
+        attr name = "foo";
+        val doc {}
+        arr params {
+            attr TypeVar = "`T";
+        }
+    });
+    assert_eq!(
+        "obj Function:\n  attr name='foo'\n  value doc:\n  arr params:\n    attr TypeVar='`T'\n",
+        t.render_with("  ")
+    );
+    assert_eq!(
+        "obj Function:\n    attr name='foo'\n    value doc:\n    arr params:\n        attr TypeVar='`T'\n",
+        t.render_with("    ")
+    );
+    assert_eq!(t.to_string(), t.render_with("   "));
+}
+
+#[test]
+pub fn test_tokens_display_as_source() {
+    let source = "use uses struct end slot meth fun is var init if else loop alias const \
+        local true false | << <- -> { } [ ] #[ ]# #{ }# [[ ]] ( ) -- : :: , \
+        foo @A `T 17 2.5e3 \"hi\" 'c'";
+    let mut count = 0;
+    for result in lex::Scanner::new("foo".to_string(), source) {
+        let (start, tok, end) = result.unwrap();
+        assert_eq!(&source[start..end], tok.to_string());
+        count += 1;
+    }
+    assert_eq!(45, count);
+    assert_eq!("end of input", lex::Tok::EOF.to_string());
+}
+
+#[test]
+pub fn test_multibyte_token_ends() {
+    let source = "x ≠ y @αβ ≤≥ 'é'";
+    let lex = lex::Scanner::new("foo".to_string(), source);
+    let tokens: Vec<(usize, lex::Tok, usize)> = lex.map(|r| r.unwrap()).collect();
+    assert_eq!(
+        vec![
+            (0, lex::Tok::SYMBOL("x".to_string()), 1),
+            (2, lex::Tok::SYMBOL("≠".to_string()), 5),
+            (6, lex::Tok::SYMBOL("y".to_string()), 7),
+            (8, lex::Tok::STACKVAR("@αβ".to_string()), 13),
+            (14, lex::Tok::SYMBOL("≤≥".to_string()), 20),
+            (21, lex::Tok::CHARLIT('é'), 25),
+        ],
+        tokens
+    );
+    for (_, _, end) in &tokens {
+        assert!(source.is_char_boundary(*end));
+    }
+    let scanner = lex::Scanner::new("foo".to_string(), source);
+    assert_eq!((1, 4), scanner.line_and_col(5));
+    assert_eq!((1, 13), scanner.line_and_col(20));
+}
+
+#[test]
+pub fn test_scan_type_var_chars() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "`a1 `t_2 `T<< Foo `1");
+    assert_token_is(lex.scan_token(), lex::Tok::TYPEVAR("`a1".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::TYPEVAR("`t_2".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::TYPEVAR("`T".to_string()));
+    assert_token_is(lex.scan_token(), lex::Tok::SUBTYPE);
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("Foo".to_string()));
+    assert!(matches!(lex.scan_token(), Some(Err(_))));
+
+    let parsed = crate::parse_decl("fun [`elem_ty] f ( `elem_ty -- ) is drop end").unwrap();
+    let ast::Decl::Function(f) = parsed else {
+        panic!("Expected a function declaration")
+    };
+    assert_eq!("`elem_ty", f.type_params.unwrap()[0].name.0);
+}
+
+#[test]
+pub fn test_mismatched_delimiters() {
+    match crate::parse_decl("fun f ( -- ) is\n    #[ int | 1 ] end") {
+        Err(Error::ParseError {
+            line,
+            column,
+            message,
+            expected,
+            ..
+        }) => {
+            assert_eq!((2, 16), (line, column));
+            assert_eq!(
+                "Mismatched ']': expected ']#' to close the '#[' at line 2, column 5",
+                message
+            );
+            assert_eq!(vec!["]#"], expected);
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+
+    let check = |text: &str| {
+        lex::Scanner::new("foo".to_string(), text)
+            .check_delimiters()
+            .map_err(|e| e.offset())
+    };
+    assert_eq!(Ok(()), check("[[ ( -- ) #{ int, str | (1, \"a\") }# ]]"));
+    assert_eq!(Err(4), check("( ) ]"));
+    assert_eq!(Err(0), check("( [[ ]]"));
+}
+
+#[test]
+pub fn test_scan_ascii_operators() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "+-*/<=>!?$%^&~ a.b_c9");
+    assert_token_is(
+        lex.scan_token(),
+        lex::Tok::SYMBOL("+-*/<=>!?$%^&~".to_string()),
+    );
+    assert_token_is(lex.scan_token(), lex::Tok::SYMBOL("a.b_c9".to_string()));
+    assert!(lex.scan_token().is_none());
+}
+
+#[test]
+pub fn test_scan_lone_sigils() {
+    for (source, message) in [
+        ("`", "'`' must be followed by a letter, but saw EOF"),
+        ("x ` y", "'`' must be followed by a letter, but saw ' '"),
+        ("x `1", "'`' must be followed by a letter, but saw '1'"),
+        ("x @\n", "'@' must be followed by a letter, but saw '\\n'"),
+    ] {
+        let err = lex::Scanner::new("foo".to_string(), source)
+            .find_map(|r| r.err())
+            .unwrap();
+        match err {
+            Error::LexicalError {
+                offset, message: m, ..
+            } => {
+                assert_eq!(message, m);
+                assert_eq!(source.rfind(['`', '@']).unwrap(), offset);
+            }
+            e => panic!("Expected a lexical error, got {:?}", e),
+        }
+    }
+}
+
+#[test]
+pub fn test_scan_bang() {
+    let mut lex = lex::Scanner::new("foo".to_string(), "set! ! a!b foo !");
+    for expected in ["set!", "!", "a!b", "foo", "!"] {
+        assert_token_is(lex.scan_token(), lex::Tok::SYMBOL(expected.to_string()));
+    }
+    assert!(lex.scan_token().is_none());
+
+    let parsed = crate::parse_expr("!").unwrap();
+    assert!(matches!(parsed, ast::Expr::FunCall(c)
+        if c.id == ast::Identifier::Simple(ast::Symbol("!".to_string()))));
+}
+
+#[test]
+pub fn test_definition_metrics() {
+    let metrics = |text: &str| match crate::parse_decl(text).unwrap() {
+        ast::Decl::Function(f) => f.metrics(),
+        _ => panic!("Expected a function declaration"),
+    };
+
+    let flat = metrics("fun f ( int -- int ) is 1 + dup * lib::sq end");
+    assert_eq!(5, flat.statement_count);
+    assert_eq!(0, flat.max_nesting);
+    assert_eq!(
+        ["+", "dup", "*", "lib::sq"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<std::collections::HashSet<String>>(),
+        flat.invoked_names
+    );
+
+    let nested = metrics(
+        "fun g ( int -- ) is
+            loop
+                dup 0 > if
+                    1 - print
+                else
+                    drop
+                end
+            end
+            if 1 else 2 end
+        end",
+    );
+    assert_eq!(12, nested.statement_count);
+    assert_eq!(2, nested.max_nesting);
+    assert_eq!(5, nested.invoked_names.len());
+}
+
+#[test]
+pub fn test_parse_cond_without_else() {
+    let parsed = crate::parse_expr("if 1 print end").unwrap();
+    let ast::Expr::Cond(c) = &parsed else {
+        panic!("Expected a cond")
+    };
+    assert_eq!(2, c.true_block.len());
+    assert!(c.false_block.is_empty());
+    assert_eq!(ast::Span::new(0, 14), c.span);
+    assert_eq!("   if\n      1\n      print\n   end\n", parsed.to_string());
+
+    let source = crate::format::ToSource::to_source(&parsed);
+    assert_eq!("if\n   1\n   print\nend", source);
+    assert_eq!(
+        parsed.to_string(),
+        crate::parse_expr(&source).unwrap().to_string()
+    );
+
+    assert!(crate::parse_expr("if 1 else end").is_err());
+}
@@ -2,7 +2,7 @@ pub trait Twistable {
     fn twist(&self) -> Twist;
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Twist {
     ObjNode(String, Vec<Twist>),
     ArrayNode(String, Vec<Twist>),
@@ -34,57 +34,143 @@ impl Twist {
         return Self::ValueNode(name.to_string(), Some(Box::new(value)));
     }
 
-    fn indent(s: &mut String, i: usize) {
-        s.push_str(&"   ".repeat(i))
+    /// The unit of indentation used by "render" and "to_string".
+    const INDENT: &'static str = "   ";
+
+    /// Escape an attribute value so that it fits on one line between
+    /// single quotes: backslashes and quotes get a backslash in front,
+    /// newlines, returns and tabs become "\n", "\r" and "\t", and any
+    /// other control character becomes a "\u{..}" escape with its code
+    /// in hex.
+    fn escape_attr(s: &str) -> String {
+        let mut escaped = String::new();
+        for c in s.chars() {
+            match c {
+                '\'' => escaped.push_str("\\'"),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        return escaped;
     }
 
+    /// Undo "escape_attr".
+    fn unescape_attr(s: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('u') => {
+                    if chars.next() != Some('{') {
+                        return Err("bad \\u escape".to_string());
+                    }
+                    let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    let Some(ch) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                    else {
+                        return Err("bad \\u escape".to_string());
+                    };
+                    result.push(ch);
+                }
+                Some(c) => result.push(c),
+                None => return Err("trailing backslash".to_string()),
+            }
+        }
+        return Ok(result);
+    }
+
+    /// Render the tree as indented text, one node per line, starting
+    /// at the given indentation level. Attribute values are escaped
+    /// (see "escape_attr"), so "parse" can read the text back exactly.
     pub fn render(&self, rendered: &mut String, indent: usize) {
+        self.render_indented(rendered, indent, Self::INDENT)
+    }
+
+    /// Render the tree from the left margin like "to_string", but
+    /// indenting each level by "indent_unit" instead of three spaces.
+    /// Only the default indentation can be read back by "parse".
+    pub fn render_with(&self, indent_unit: &str) -> String {
+        let mut s = String::new();
+        self.render_indented(&mut s, 0, indent_unit);
+        return s;
+    }
+
+    fn render_indented(&self, rendered: &mut String, indent: usize, unit: &str) {
         match self {
             Self::ObjNode(name, children) => {
-                Self::indent(rendered, indent);
+                rendered.push_str(&unit.repeat(indent));
                 rendered.push_str("obj ");
                 rendered.push_str(name);
                 rendered.push_str(":\n");
                 for c in children {
-                    c.render(rendered, indent + 1)
+                    c.render_indented(rendered, indent + 1, unit)
                 }
             }
             Self::ArrayNode(name, children) => {
-                Self::indent(rendered, indent);
+                rendered.push_str(&unit.repeat(indent));
                 rendered.push_str("arr ");
                 rendered.push_str(name);
                 rendered.push_str(":\n");
                 for c in children {
-                    c.render(rendered, indent + 1);
+                    c.render_indented(rendered, indent + 1, unit);
                 }
             }
             Self::AttrNode(name, value) => {
-                Self::indent(rendered, indent);
+                rendered.push_str(&unit.repeat(indent));
                 rendered.push_str("attr ");
                 rendered.push_str(name);
                 rendered.push_str("='");
-                rendered.push_str(value);
+                rendered.push_str(&Self::escape_attr(value));
                 rendered.push_str("'\n");
             }
-            Self::ValueNode(name, value) => match value {
-                Some(v) => {
-                    Self::indent(rendered, indent);
-                    rendered.push_str("value ");
-                    rendered.push_str(name);
-                    rendered.push_str(":\n");
-                    v.render(rendered, indent + 1)
+            Self::ValueNode(name, value) => {
+                // An empty value is rendered as a header with nothing
+                // beneath it, so that it can be read back by "parse".
+                rendered.push_str(&unit.repeat(indent));
+                rendered.push_str("value ");
+                rendered.push_str(name);
+                rendered.push_str(":\n");
+                if let Some(v) = value {
+                    v.render_indented(rendered, indent + 1, unit)
                 }
-                None => (),
-            },
+            }
         }
     }
 
+    /// Render the tree starting at the left margin.
     pub fn to_string(&self) -> String {
         let mut s = String::new();
-        self.render(&mut s, 1);
+        self.render(&mut s, 0);
         return s;
     }
 
+    /// Escape a string so that it can be placed between double quotes
+    /// in generated Rust code.
+    fn escape_code(s: &str) -> String {
+        let mut escaped = String::new();
+        for c in s.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c => escaped.push(c),
+            }
+        }
+        return escaped;
+    }
+
     pub fn code(&self, rendered: &mut String) {
         match self {
             Self::ObjNode(name, children) => {
@@ -126,7 +212,7 @@ impl Twist {
                 rendered.push_str("Twist::attr(\"");
                 rendered.push_str(name);
                 rendered.push_str("\", \"");
-                rendered.push_str(value);
+                rendered.push_str(&Self::escape_code(value));
                 rendered.push_str("\".to_string())")
             }
             Self::ValueNode(name, value) => match value {
@@ -137,9 +223,302 @@ impl Twist {
                     v.code(rendered);
                     rendered.push_str(")");
                 }
-                None => (),
+                None => {
+                    rendered.push_str("Twist::opt_val(\"");
+                    rendered.push_str(name);
+                    rendered.push_str("\", None)");
+                }
+            },
+        }
+    }
+
+    /// Write a string as a quoted JSON string.
+    pub fn json_string(rendered: &mut String, s: &str) {
+        rendered.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => rendered.push_str("\\\""),
+                '\\' => rendered.push_str("\\\\"),
+                '\n' => rendered.push_str("\\n"),
+                '\r' => rendered.push_str("\\r"),
+                '\t' => rendered.push_str("\\t"),
+                c if (c as u32) < 0x20 => rendered.push_str(&format!("\\u{:04x}", c as u32)),
+                c => rendered.push(c),
+            }
+        }
+        rendered.push('"');
+    }
+
+    fn json_children(rendered: &mut String, kind: &str, name: &str, children: &[Twist]) {
+        rendered.push_str("{\"kind\":\"");
+        rendered.push_str(kind);
+        rendered.push_str("\",\"name\":");
+        Self::json_string(rendered, name);
+        rendered.push_str(",\"children\":[");
+        for (i, c) in children.iter().enumerate() {
+            if i > 0 {
+                rendered.push(',');
+            }
+            c.json(rendered);
+        }
+        rendered.push_str("]}");
+    }
+
+    /// Render the tree as JSON. Every node is an object with a "kind"
+    /// of "obj", "arr", "attr" or "value", and a "name". Objects and
+    /// arrays have a "children" list, which is always present even when
+    /// it's empty; attrs have a string "value", and value nodes have
+    /// a "value" that is either a node or null.
+    pub fn json(&self, rendered: &mut String) {
+        match self {
+            Self::ObjNode(name, children) => Self::json_children(rendered, "obj", name, children),
+            Self::ArrayNode(name, children) => Self::json_children(rendered, "arr", name, children),
+            Self::AttrNode(name, value) => {
+                rendered.push_str("{\"kind\":\"attr\",\"name\":");
+                Self::json_string(rendered, name);
+                rendered.push_str(",\"value\":");
+                Self::json_string(rendered, value);
+                rendered.push('}');
+            }
+            Self::ValueNode(name, value) => {
+                rendered.push_str("{\"kind\":\"value\",\"name\":");
+                Self::json_string(rendered, name);
+                rendered.push_str(",\"value\":");
+                match value {
+                    Some(v) => v.json(rendered),
+                    None => rendered.push_str("null"),
+                }
+                rendered.push('}');
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        self.json(&mut s);
+        return s;
+    }
+
+    fn dot_label(s: &str) -> String {
+        return s
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+    }
+
+    /// Emit this node, and everything beneath it, as GraphViz
+    /// statements; returns the id of the node that was generated
+    /// for it, so that the caller can draw an edge to it.
+    pub fn dot(&self, rendered: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        match self {
+            Self::ObjNode(name, children) | Self::ArrayNode(name, children) => {
+                let kind = if let Self::ObjNode(_, _) = self {
+                    "obj"
+                } else {
+                    "arr"
+                };
+                rendered.push_str(&format!(
+                    "   n{} [shape=box, label=\"{} {}\"];\n",
+                    id,
+                    kind,
+                    Self::dot_label(name)
+                ));
+                for c in children {
+                    let child = c.dot(rendered, next_id);
+                    rendered.push_str(&format!("   n{} -> n{};\n", id, child));
+                }
+            }
+            Self::AttrNode(name, value) => {
+                rendered.push_str(&format!(
+                    "   n{} [shape=ellipse, label=\"{}={}\"];\n",
+                    id,
+                    Self::dot_label(name),
+                    Self::dot_label(value)
+                ));
+            }
+            Self::ValueNode(name, value) => {
+                rendered.push_str(&format!(
+                    "   n{} [shape=plaintext, label=\"{}\"];\n",
+                    id,
+                    Self::dot_label(name)
+                ));
+                if let Some(v) = value {
+                    let child = v.dot(rendered, next_id);
+                    rendered.push_str(&format!("   n{} -> n{};\n", id, child));
+                }
+            }
+        }
+        return id;
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut s = String::from("digraph twist {\n");
+        self.dot(&mut s, &mut 0);
+        s.push_str("}\n");
+        return s;
+    }
+
+    fn describe(&self) -> String {
+        return match self {
+            Self::ObjNode(name, _) => format!("obj {}", name),
+            Self::ArrayNode(name, _) => format!("arr {}", name),
+            Self::AttrNode(name, value) => format!("attr {}='{}'", name, value),
+            Self::ValueNode(name, Some(_)) => format!("value {}", name),
+            Self::ValueNode(name, None) => format!("empty value {}", name),
+        };
+    }
+
+    fn path_to(path: &str, step: &str) -> String {
+        if path.is_empty() {
+            return step.to_string();
+        } else {
+            return format!("{}/{}", path, step);
+        }
+    }
+
+    fn diff_children(
+        path: &str,
+        child_path: &dyn Fn(usize) -> String,
+        expected: &[Twist],
+        found: &[Twist],
+    ) -> Option<String> {
+        for (i, (e, f)) in expected.iter().zip(found.iter()).enumerate() {
+            let d = e.diff_at(f, &child_path(i));
+            if d.is_some() {
+                return d;
+            }
+        }
+        if expected.len() != found.len() {
+            return Some(format!(
+                "{}: expected {} children, found {}",
+                path,
+                expected.len(),
+                found.len()
+            ));
+        }
+        return None;
+    }
+
+    fn diff_at(&self, other: &Twist, path: &str) -> Option<String> {
+        match (self, other) {
+            (Self::ObjNode(name, children), Self::ObjNode(other_name, other_children))
+                if name == other_name =>
+            {
+                let path = Self::path_to(path, name);
+                return Self::diff_children(&path, &|_| path.clone(), children, other_children);
+            }
+            (Self::ArrayNode(name, children), Self::ArrayNode(other_name, other_children))
+                if name == other_name =>
+            {
+                return Self::diff_children(
+                    path,
+                    &|i| Self::path_to(path, &format!("{}[{}]", name, i)),
+                    children,
+                    other_children,
+                );
+            }
+            (Self::AttrNode(name, value), Self::AttrNode(other_name, other_value))
+                if name == other_name && value == other_value =>
+            {
+                return None;
+            }
+            (Self::ValueNode(name, value), Self::ValueNode(other_name, other_value))
+                if name == other_name =>
+            {
+                match (value, other_value) {
+                    (Some(v), Some(ov)) => return v.diff_at(ov, &Self::path_to(path, name)),
+                    (None, None) => return None,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        return Some(format!(
+            "{}: expected {}, found {}",
+            path,
+            self.describe(),
+            other.describe()
+        ));
+    }
+
+    /// Compare two trees, returning a description of the first place
+    /// where they differ, or None if they're the same. The description
+    /// starts with a "/"-separated path to the node that differs.
+    pub fn diff(&self, other: &Twist) -> Option<String> {
+        return self.diff_at(other, "");
+    }
+
+    /// Split a line of rendered twist into its indentation level and
+    /// its contents.
+    fn parse_line(line: &str) -> (usize, &str) {
+        let content = line.trim_start_matches(' ');
+        return ((line.len() - content.len()) / Self::INDENT.len(), content);
+    }
+
+    fn parse_node(lines: &[&str], pos: &mut usize) -> Result<Twist, String> {
+        let (level, content) = Self::parse_line(lines[*pos]);
+        let line_num = *pos + 1;
+        *pos += 1;
+        if let Some(attr) = content.strip_prefix("attr ") {
+            let Some((name, value)) = attr.split_once("='") else {
+                return Err(format!("line {}: expected name='value' in attr", line_num));
+            };
+            let Some(value) = value.strip_suffix('\'') else {
+                return Err(format!("line {}: unterminated attr value", line_num));
+            };
+            let value = Self::unescape_attr(value)
+                .map_err(|e| format!("line {}: {} in attr value", line_num, e))?;
+            return Ok(Self::AttrNode(name.to_string(), value));
+        }
+        let Some((kind, name)) = content.strip_suffix(':').and_then(|c| c.split_once(' ')) else {
+            return Err(format!(
+                "line {}: unrecognized node {:?}",
+                line_num, content
+            ));
+        };
+        let mut children = Vec::new();
+        while *pos < lines.len() && Self::parse_line(lines[*pos]).0 > level {
+            if Self::parse_line(lines[*pos]).0 != level + 1 {
+                return Err(format!("line {}: bad indentation", *pos + 1));
+            }
+            children.push(Self::parse_node(lines, pos)?);
+        }
+        return match kind {
+            "obj" => Ok(Self::ObjNode(name.to_string(), children)),
+            "arr" => Ok(Self::ArrayNode(name.to_string(), children)),
+            "value" => match children.len() {
+                0 => Ok(Self::ValueNode(name.to_string(), None)),
+                1 => Ok(Self::ValueNode(
+                    name.to_string(),
+                    children.pop().map(Box::new),
+                )),
+                _ => Err(format!(
+                    "line {}: value {} has more than one child",
+                    line_num, name
+                )),
             },
+            _ => Err(format!("line {}: unknown node kind {:?}", line_num, kind)),
+        };
+    }
+
+    /// Read a tree back in from the text produced by "render" or
+    /// "to_string".
+    pub fn parse(s: &str) -> Result<Twist, String> {
+        let lines: Vec<&str> = s.lines().filter(|l| !l.trim().is_empty()).collect();
+        if lines.is_empty() {
+            return Err("no twist to parse".to_string());
+        }
+        let mut pos = 0;
+        let result = Self::parse_node(&lines, &mut pos)?;
+        if pos < lines.len() {
+            return Err(format!(
+                "line {}: unexpected text after the root node",
+                pos + 1
+            ));
         }
+        return Ok(result);
     }
 }
 
@@ -148,3 +527,51 @@ impl Twistable for Twist {
         self.clone()
     }
 }
+
+/// A more compact way of writing out a Twist tree by hand, mainly
+/// for tests. Each node is written as one of:
+///
+///   obj Name { children }
+///   arr name { children }
+///   attr name = value;
+///   val name { node }
+///   val name {}
+///
+/// where the children are a sequence of nodes, and a name can be
+/// either an identifier or a string literal. An empty "val" is the
+/// same as "Twist::opt_val(name, None)".
+#[macro_export]
+macro_rules! twist {
+    (@name $name:ident) => { stringify!($name) };
+    (@name $name:literal) => { $name };
+
+    (@children [$($done:expr),*]) => { vec![$($done),*] };
+    (@children [$($done:expr),*] obj $name:tt { $($body:tt)* } $($rest:tt)*) => {
+        $crate::twist!(@children [$($done,)* $crate::twist!(obj $name { $($body)* })] $($rest)*)
+    };
+    (@children [$($done:expr),*] arr $name:tt { $($body:tt)* } $($rest:tt)*) => {
+        $crate::twist!(@children [$($done,)* $crate::twist!(arr $name { $($body)* })] $($rest)*)
+    };
+    (@children [$($done:expr),*] val $name:tt { $($body:tt)* } $($rest:tt)*) => {
+        $crate::twist!(@children [$($done,)* $crate::twist!(val $name { $($body)* })] $($rest)*)
+    };
+    (@children [$($done:expr),*] attr $name:tt = $value:expr; $($rest:tt)*) => {
+        $crate::twist!(@children [$($done,)* $crate::twist!(attr $name = $value)] $($rest)*)
+    };
+
+    (obj $name:tt { $($body:tt)* }) => {
+        $crate::twist::Twist::obj($crate::twist!(@name $name), $crate::twist!(@children [] $($body)*))
+    };
+    (arr $name:tt { $($body:tt)* }) => {
+        $crate::twist::Twist::arr($crate::twist!(@name $name), $crate::twist!(@children [] $($body)*))
+    };
+    (attr $name:tt = $value:expr) => {
+        $crate::twist::Twist::attr($crate::twist!(@name $name), ($value).to_string())
+    };
+    (val $name:tt {}) => {
+        $crate::twist::Twist::opt_val($crate::twist!(@name $name), None)
+    };
+    (val $name:tt { $($node:tt)+ }) => {
+        $crate::twist::Twist::val($crate::twist!(@name $name), $crate::twist!($($node)+))
+    };
+}
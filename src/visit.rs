@@ -0,0 +1,164 @@
+use crate::ast::{Decl, Expr, MethodDecl, SType, Sect, StackEffect};
+
+/// A visitor for walking the AST. Each method's default implementation
+/// just calls the matching "walk_" function, which visits the node's
+/// children; a pass overrides the methods for the nodes that it
+/// cares about, and calls the "walk_" function itself if it still
+/// wants to descend into the children.
+pub trait Visitor: Sized {
+    fn visit_sect(&mut self, sect: &Sect) {
+        walk_sect(self, sect)
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_method(&mut self, method: &MethodDecl) {
+        walk_method(self, method)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_type(&mut self, s_type: &SType) {
+        walk_type(self, s_type)
+    }
+
+    fn visit_stack_effect(&mut self, effect: &StackEffect) {
+        walk_stack_effect(self, effect)
+    }
+}
+
+pub fn walk_sect<V: Visitor>(visitor: &mut V, sect: &Sect) {
+    for d in &sect.decls {
+        visitor.visit_decl(d);
+    }
+}
+
+pub fn walk_decl<V: Visitor>(visitor: &mut V, decl: &Decl) {
+    match decl {
+        Decl::Struct(s) => {
+            for st in s.supers.iter().flatten() {
+                visitor.visit_type(st);
+            }
+            for tp in s.type_params.iter().flatten() {
+                if let Some(c) = &tp.constraint {
+                    visitor.visit_type(c);
+                }
+            }
+            for f in &s.fields {
+                visitor.visit_type(&f.s_type);
+            }
+            for m in &s.methods {
+                visitor.visit_method(m);
+            }
+        }
+        Decl::Function(f) => {
+            for tp in f.type_params.iter().flatten() {
+                if let Some(c) = &tp.constraint {
+                    visitor.visit_type(c);
+                }
+            }
+            visitor.visit_stack_effect(&f.signature);
+            for e in &f.body {
+                visitor.visit_expr(e);
+            }
+        }
+        Decl::Var(v) => {
+            visitor.visit_type(&v.s_type);
+            for e in &v.init_value {
+                visitor.visit_expr(e);
+            }
+        }
+        Decl::Const(c) => {
+            visitor.visit_type(&c.value_type);
+            for e in &c.body {
+                visitor.visit_expr(e);
+            }
+        }
+        Decl::Alias(a) => {
+            for tp in a.type_params.iter().flatten() {
+                if let Some(c) = &tp.constraint {
+                    visitor.visit_type(c);
+                }
+            }
+            visitor.visit_type(&a.aliased);
+        }
+    }
+}
+
+pub fn walk_method<V: Visitor>(visitor: &mut V, method: &MethodDecl) {
+    visitor.visit_stack_effect(&method.effect);
+    for e in &method.body {
+        visitor.visit_expr(e);
+    }
+}
+
+pub fn walk_expr<V: Visitor>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::FunCall(f) => {
+            for t in f.type_args.iter().flatten() {
+                visitor.visit_type(t);
+            }
+        }
+        Expr::List(l) => {
+            visitor.visit_type(&l.value_type);
+            for e in l.values.iter().flatten() {
+                visitor.visit_expr(e);
+            }
+        }
+        Expr::Map(m) => {
+            visitor.visit_type(&m.key_type);
+            visitor.visit_type(&m.value_type);
+            for (k, v) in &m.values {
+                for e in k.iter().chain(v.iter()) {
+                    visitor.visit_expr(e);
+                }
+            }
+        }
+        Expr::Cond(c) => {
+            for e in c.true_block.iter().chain(c.false_block.iter()) {
+                visitor.visit_expr(e);
+            }
+        }
+        Expr::Loop(l) => {
+            for e in &l.body {
+                visitor.visit_expr(e);
+            }
+        }
+        Expr::Block(b) => {
+            visitor.visit_stack_effect(&b.effect);
+            for e in &b.body {
+                visitor.visit_expr(e);
+            }
+        }
+        Expr::MethodCall(_)
+        | Expr::IntLit(_, _)
+        | Expr::FloatLit(_, _)
+        | Expr::StringLit(_, _)
+        | Expr::CharLit(_, _)
+        | Expr::BoolLit(_, _)
+        | Expr::Local(_)
+        | Expr::LocalRef(_) => (),
+    }
+}
+
+pub fn walk_type<V: Visitor>(visitor: &mut V, s_type: &SType) {
+    match s_type {
+        SType::Parametric(params, _) => {
+            for p in params {
+                visitor.visit_type(p);
+            }
+        }
+        SType::Function(effect) => visitor.visit_stack_effect(effect),
+        SType::Simple(_) | SType::TypeVar(_) => (),
+    }
+}
+
+pub fn walk_stack_effect<V: Visitor>(visitor: &mut V, effect: &StackEffect) {
+    for t in effect.before.stack.iter().chain(effect.after.stack.iter()) {
+        visitor.visit_type(t);
+    }
+}